@@ -2,9 +2,11 @@ use lps::*;
 
 use std::sync::Arc;
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 struct TestMsg0(i32);
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 struct TestMsg1(i32);
 
@@ -68,6 +70,12 @@ impl Subscriber for TestSubsciber0 {
             })
             .run();
     }
+
+    fn process_messages_max(&mut self, max: usize) -> ProcessedMessages {
+        self.sub.process_messages_max(max, |msg| {
+            println!("test_sub0.sub: {:?}", msg);
+        })
+    }
 }
 
 struct TestSubsciber1 {
@@ -111,6 +119,25 @@ impl Subscriber for TestSubsciber1 {
             })
             .run();
     }
+
+    fn process_messages_max(&mut self, max: usize) -> ProcessedMessages {
+        let handled = self
+            .sub
+            .message_iter()
+            .limit(max)
+            .handle(|msg: Arc<TestMsg0>| {
+                println!("test_sub1.sub0: {:?}", msg);
+            })
+            .handle(|msg: Arc<TestMsg1>| {
+                println!("test_sub1.sub1: {:?}", msg);
+            })
+            .count();
+
+        ProcessedMessages {
+            handled,
+            has_more: self.sub.pending_messages() > 0,
+        }
+    }
 }
 
 fn main() {
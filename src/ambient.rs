@@ -0,0 +1,88 @@
+use crate::*;
+
+use std::cell::RefCell;
+use std::sync::{Arc, RwLock};
+
+// The message broker which is used when no broker is given explicitly or in scope.
+static GLOBAL_BROKER: RwLock<Option<Arc<dyn MessageBroker>>> = RwLock::new(None);
+
+thread_local! {
+    // Message brokers set by [`with_broker`] on this thread, the innermost one is the last.
+    static SCOPED_BROKERS: RefCell<Vec<Arc<dyn MessageBroker>>> = const { RefCell::new(Vec::new()) };
+}
+
+// Removes the innermost scoped message broker when the scope ends, even if it panics.
+struct BrokerScope;
+
+impl Drop for BrokerScope {
+    fn drop(&mut self) {
+        SCOPED_BROKERS.with(|scoped| scoped.borrow_mut().pop());
+    }
+}
+
+/// Returns the process-global message broker.
+///
+/// A [`DefaultMessageBroker`] is created on the first call unless another broker
+/// was set with [`set_global_broker`].
+pub fn global_broker() -> Arc<dyn MessageBroker> {
+    if let Some(ref msg_broker) = *GLOBAL_BROKER.read().unwrap_or_else(|err| err.into_inner()) {
+        return Arc::clone(msg_broker);
+    }
+
+    let mut global_broker = GLOBAL_BROKER.write().unwrap_or_else(|err| err.into_inner());
+    let msg_broker = global_broker.get_or_insert_with(|| Arc::new(DefaultMessageBroker::new()));
+
+    Arc::clone(msg_broker)
+}
+
+/// Replaces the process-global message broker and returns the previous one.
+///
+/// Subscriptions and publishers which already use the previous broker keep using it.
+pub fn set_global_broker(msg_broker: Arc<dyn MessageBroker>) -> Option<Arc<dyn MessageBroker>> {
+    GLOBAL_BROKER
+        .write()
+        .unwrap_or_else(|err| err.into_inner())
+        .replace(msg_broker)
+}
+
+/// Makes the given message broker the ambient one on the current thread while `f` runs.
+///
+/// Scopes can be nested, the innermost broker is used.
+pub fn with_broker<R>(msg_broker: Arc<dyn MessageBroker>, f: impl FnOnce() -> R) -> R {
+    SCOPED_BROKERS.with(|scoped| scoped.borrow_mut().push(msg_broker));
+    let _scope = BrokerScope;
+
+    f()
+}
+
+/// Returns the ambient message broker: the innermost one set with [`with_broker`]
+/// on the current thread, or the [`global_broker`] if there is none.
+pub fn current_broker() -> Arc<dyn MessageBroker> {
+    SCOPED_BROKERS
+        .with(|scoped| scoped.borrow().last().cloned())
+        .unwrap_or_else(global_broker)
+}
+
+/// A [`Publisher`] which sends messages to the ambient message broker
+/// (see [`current_broker`]) unless a message broker is set explicitly.
+#[derive(Default)]
+pub struct AmbientPublisher {
+    msg_broker: Option<Arc<dyn MessageBroker>>,
+}
+
+impl AmbientPublisher {
+    /// Creates a new [`AmbientPublisher`] which sends messages to the ambient message broker.
+    pub fn new() -> Self {
+        Self { msg_broker: None }
+    }
+}
+
+impl Publisher for AmbientPublisher {
+    fn message_broker(&self) -> Arc<dyn MessageBroker> {
+        self.msg_broker.clone().unwrap_or_else(current_broker)
+    }
+
+    fn set_message_broker(&mut self, msg_broker: Arc<dyn MessageBroker>) {
+        self.msg_broker = Some(msg_broker);
+    }
+}
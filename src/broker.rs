@@ -1,7 +1,11 @@
 use crate::*;
 
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[doc(hidden)]
 pub trait AsMessageBroker {
@@ -16,9 +20,28 @@ impl<B: MessageBroker + 'static> AsMessageBroker for B {
 }
 
 /// A type which delivers messages from publishers to subscribers.
-pub trait MessageBroker: AsMessageBroker {
+///
+/// Message brokers are shared through [`Arc<dyn MessageBroker>`] by subscriptions,
+/// dispatchers and schedulers which can be moved to other threads,
+/// so they must be [`Send`] and [`Sync`].
+pub trait MessageBroker: AsMessageBroker + Send + Sync {
     /// Gets [`MessageTopic`] which is responsible for handling messages of the given type.
     fn get_message_topic(&self, msg_type_id: MessageTypeId) -> Arc<MessageTopic>;
+    /// Gets [`MessageTopic`] which is responsible for handling messages of the given type
+    /// if it exists, without creating it.
+    ///
+    /// Publishing uses this method, so messages nobody listens for don't create topics.
+    /// The default implementation calls [`MessageBroker::get_message_topic`].
+    fn find_message_topic(&self, msg_type_id: MessageTypeId) -> Option<Arc<MessageTopic>> {
+        Some(self.get_message_topic(msg_type_id))
+    }
+    /// Lets the broker reclaim the [`MessageTopic`] of the given type
+    /// after one of its message channels was destroyed.
+    ///
+    /// The default implementation does nothing.
+    fn release_message_topic(&self, msg_type_id: MessageTypeId) {
+        let _ = msg_type_id;
+    }
 
     /// Registers the subscription in the broker.
     ///
@@ -28,7 +51,7 @@ pub trait MessageBroker: AsMessageBroker {
         sub: &mut dyn ErasedSubscription,
     ) -> Result<(), MessageBrokerError> {
         sub.register(self.as_message_broker())
-            .map_err(|sub_err| MessageBrokerError::SubscriptionError(sub_err))
+            .map_err(MessageBrokerError::SubscriptionError)
     }
 
     /// Unregisters the subscription in the broker.
@@ -39,59 +62,517 @@ pub trait MessageBroker: AsMessageBroker {
         sub: &mut dyn ErasedSubscription,
     ) -> Result<(), MessageBrokerError> {
         sub.unregister()
-            .map_err(|sub_err| MessageBrokerError::SubscriptionError(sub_err))
+            .map_err(MessageBrokerError::SubscriptionError)
     }
 
     /// Sends the given message to all subscribers which are listening for messages of its type.
+    ///
+    /// Messages which can't be delivered are passed to [`MessageBroker::dead_letter`],
+    /// once for each subscription which rejected them. Publishing fails only if
+    /// no subscription received the message.
     fn publish_message(&self, msg: Arc<dyn Message>) -> Result<(), MessageBrokerError> {
-        self.get_message_topic(msg.type_id())
-            .send_message(msg)
-            .map_err(|msg_topic_err| MessageBrokerError::MessageTopicError(msg_topic_err))
+        match self.find_message_topic(msg.type_id()) {
+            Some(msg_topic) => deliver_message(self, &msg_topic, msg),
+            None => {
+                let msg_topic_err = missing_topic_error(self, msg.as_ref());
+                handle_delivery_result(self, msg, Err(msg_topic_err))
+            }
+        }
+    }
+
+    /// Sends the given messages to all subscribers which are listening for messages
+    /// of their types.
+    ///
+    /// Messages of the same type are put into the queue of each subscriber contiguously
+    /// in the order they are given. All messages are sent even if some of them fail,
+    /// in that case the first error is returned.
+    fn publish_message_batch(&self, msgs: Vec<Arc<dyn Message>>) -> Result<(), MessageBrokerError> {
+        deliver_messages(self, msgs)
+    }
+
+    /// Sends the message which couldn't be delivered or handled to the subscribers
+    /// which are listening for [`DeadLetter`]s.
+    ///
+    /// Dead letters themselves are never wrapped into another [`DeadLetter`].
+    fn dead_letter(&self, dead_letter: DeadLetter) {
+        if dead_letter.message().type_id() == MessageTypeId::of::<DeadLetter>() {
+            return;
+        }
+
+        if let Some(msg_topic) = self.find_message_topic(MessageTypeId::of::<DeadLetter>()) {
+            let _ = msg_topic.send_message(Arc::new(dead_letter), &|| self.next_sequence_number());
+        }
+    }
+
+    /// Shuts the message broker down.
+    ///
+    /// After that the broker rejects all published messages and every [`Subscription`]
+    /// learns that it won't receive new messages (see [`ErasedSubscription::is_closed`]).
+    /// Messages which wait in subscriptions are either left to be received or discarded
+    /// depending on `mode`.
+    ///
+    /// The default implementation returns [`MessageBrokerError::Unsupported`].
+    fn shutdown(&self, mode: ShutdownMode) -> Result<ShutdownSummary, MessageBrokerError> {
+        let _ = mode;
+
+        Err(MessageBrokerError::Unsupported)
+    }
+
+    /// Returns if the message broker was shut down.
+    fn is_shut_down(&self) -> bool {
+        false
+    }
+
+    /// Returns the sequence number which is assigned to the next published message.
+    ///
+    /// Sequence numbers increase with each message published in this broker regardless
+    /// of its type, so they define the order in which messages were published.
+    ///
+    /// The default implementation takes numbers from a counter which is shared by all
+    /// brokers which don't override this method, so their numbers may have gaps.
+    fn next_sequence_number(&self) -> u64 {
+        static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+        NEXT_SEQ.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+// Sends the given message to the message topic with a new sequence number
+// and passes it to [`MessageBroker::dead_letter`] if it can't be delivered.
+pub(crate) fn deliver_message<B: MessageBroker + ?Sized>(
+    msg_broker: &B,
+    msg_topic: &MessageTopic,
+    msg: Arc<dyn Message>,
+) -> Result<(), MessageBrokerError> {
+    let res = msg_topic.send_message(Arc::clone(&msg), &|| msg_broker.next_sequence_number());
+
+    handle_delivery_result(msg_broker, msg, res)
+}
+
+// Sends the given messages, locking the topic of each message type only once.
+//
+// All topics of the batch are locked while the messages are numbered in the order they are given,
+// so subscriptions which receive messages in publish order see the order of the batch.
+// The topics are locked in the order of their type ids, so concurrent batches can't deadlock.
+pub(crate) fn deliver_messages<B: MessageBroker + ?Sized>(
+    msg_broker: &B,
+    msgs: Vec<Arc<dyn Message>>,
+) -> Result<(), MessageBrokerError> {
+    let mut msg_type_ids: Vec<MessageTypeId> = msgs.iter().map(|msg| msg.type_id()).collect();
+    msg_type_ids.sort_unstable();
+    msg_type_ids.dedup();
+
+    let msg_topics: Vec<_> = msg_type_ids
+        .iter()
+        .map(|&msg_type_id| msg_broker.find_message_topic(msg_type_id))
+        .collect();
+    let mut locked_topics: Vec<_> = msg_topics
+        .iter()
+        .map(|msg_topic| msg_topic.as_deref().map(MessageTopic::lock))
+        .collect();
+
+    // Messages without a topic get `None`, their error is found once the topics are unlocked.
+    let results: Vec<_> = msgs
+        .iter()
+        .map(|msg| {
+            let idx = msg_type_ids.binary_search(&msg.type_id()).ok()?;
+            let locked_topic = locked_topics[idx].as_mut()?;
+            Some(locked_topic.send_message(msg, msg_broker.next_sequence_number()))
+        })
+        .collect();
+    locked_topics
+        .into_iter()
+        .flatten()
+        .for_each(LockedMessageTopic::unlock);
+
+    msgs.into_iter()
+        .zip(results)
+        .map(|(msg, res)| {
+            let res = res.unwrap_or_else(|| Err(missing_topic_error(msg_broker, msg.as_ref())));
+            handle_delivery_result(msg_broker, msg, res)
+        })
+        .fold(Ok(()), Result::and)
+}
+
+// Returns why the message can't be delivered when there is no topic for its type.
+fn missing_topic_error<B: MessageBroker + ?Sized>(
+    msg_broker: &B,
+    msg: &dyn Message,
+) -> MessageTopicError {
+    let msg_type_name = msg.type_name();
+    if msg_broker.is_shut_down() {
+        MessageTopicError::Closed { msg_type_name }
+    } else {
+        MessageTopicError::NoSubscribers { msg_type_name }
+    }
+}
+
+// Sends the given messages to the message topic with sequence numbers taken
+// from `next_seq`, locking the topic only once.
+pub(crate) fn deliver_message_batch<B: MessageBroker + ?Sized>(
+    msg_broker: &B,
+    msg_topic: &MessageTopic,
+    msgs: Vec<Arc<dyn Message>>,
+    next_seq: &mut dyn FnMut() -> u64,
+) -> Result<(), MessageBrokerError> {
+    msg_topic
+        .send_messages(msgs.clone(), next_seq)
+        .into_iter()
+        .zip(msgs)
+        .map(|(res, msg)| handle_delivery_result(msg_broker, msg, res))
+        .fold(Ok(()), Result::and)
+}
+
+// Passes the message to [`MessageBroker::dead_letter`] if it wasn't delivered,
+// once for each channel which rejected it.
+//
+// Publishing fails only if no channel received the message.
+fn handle_delivery_result<B: MessageBroker + ?Sized>(
+    msg_broker: &B,
+    msg: Arc<dyn Message>,
+    res: Result<Delivery, MessageTopicError>,
+) -> Result<(), MessageBrokerError> {
+    match res {
+        Ok(delivery) => {
+            for msg_channel_err in &delivery.rejections {
+                let reason = match msg_channel_err {
+                    MessageChannelError::WrongMessageType { .. } => {
+                        DeadLetterReason::WrongMessageType
+                    }
+                    MessageChannelError::BufferFull { .. } => DeadLetterReason::BufferFull,
+                };
+                msg_broker.dead_letter(DeadLetter::new(Arc::clone(&msg), reason));
+            }
+
+            match delivery.rejections.into_iter().next() {
+                Some(msg_channel_err) if delivery.received == 0 => {
+                    Err(MessageBrokerError::MessageTopicError(
+                        MessageTopicError::MessageChannelError(msg_channel_err),
+                    ))
+                }
+                _ => Ok(()),
+            }
+        }
+        Err(MessageTopicError::NoSubscribers { .. }) => {
+            msg_broker.dead_letter(DeadLetter::new(msg, DeadLetterReason::NoSubscribers));
+
+            Ok(())
+        }
+        Err(msg_topic_err @ MessageTopicError::WrongMessageType { .. }) => {
+            msg_broker.dead_letter(DeadLetter::new(msg, DeadLetterReason::WrongMessageType));
+
+            Err(MessageBrokerError::MessageTopicError(msg_topic_err))
+        }
+        Err(msg_topic_err) => Err(MessageBrokerError::MessageTopicError(msg_topic_err)),
     }
 }
 
 impl dyn MessageBroker {
-    // Creates a new message channel in the message topic of the given generic type.
-    pub(crate) fn create_message_channel<M: Message>(&self) -> channel::MessageReceiver {
+    /// Wraps the given message into [`Arc`] and publishes it.
+    ///
+    /// See [`MessageBroker::publish_message`]
+    pub fn publish_value<M: Message>(&self, msg: M) -> Result<(), MessageBrokerError> {
+        self.publish_message(Arc::new(msg))
+    }
+
+    /// Publishes all given messages at once.
+    ///
+    /// See [`MessageBroker::publish_message_batch`]
+    pub fn publish_batch(
+        &self,
+        msgs: impl IntoIterator<Item = Arc<dyn Message>>,
+    ) -> Result<(), MessageBrokerError> {
+        self.publish_message_batch(msgs.into_iter().collect())
+    }
+
+    /// Creates a [`TypedPublisher`] which publishes messages of the given type
+    /// to this message broker.
+    pub fn publisher<M: Message>(self: &Arc<Self>) -> TypedPublisher<M> {
+        TypedPublisher::new(Arc::clone(self))
+    }
+
+    /// Sets the way messages of the given type are delivered to callbacks.
+    ///
+    /// See [`DeliveryMode`]
+    pub fn set_delivery_mode<M: Message>(&self, delivery_mode: DeliveryMode) {
+        self.get_message_topic(MessageTypeId::of::<M>())
+            .set_delivery_mode(delivery_mode);
+    }
+
+    /// Sets how long messages of the given type stay relevant after they are published.
+    ///
+    /// See [`MessageTopic::set_ttl`]
+    pub fn set_message_ttl<M: Message>(&self, ttl: Option<Duration>) {
+        self.get_message_topic(MessageTypeId::of::<M>())
+            .set_ttl(ttl);
+    }
+
+    /// Sets what happens with expired messages of the given type.
+    ///
+    /// See [`MessageTopic::set_expiry_policy`]
+    pub fn set_expiry_policy<M: Message>(&self, expiry_policy: ExpiryPolicy) {
+        self.get_message_topic(MessageTypeId::of::<M>())
+            .set_expiry_policy(expiry_policy);
+    }
+
+    /// Makes the message broker drop duplicates of messages of the given type
+    /// within the given window.
+    ///
+    /// See [`MessageTopic::set_dedup_window`]
+    pub fn set_dedup_window<M: Message>(&self, dedup_window: Option<DedupWindow>) {
+        self.get_message_topic(MessageTypeId::of::<M>())
+            .set_dedup_window(dedup_window);
+    }
+
+    /// Returns the number of messages of the given type which were checked for duplicates.
+    ///
+    /// See [`MessageTopic::dedup_stats`]
+    pub fn dedup_stats<M: Message>(&self) -> DedupStats {
+        self.find_message_topic(MessageTypeId::of::<M>())
+            .map_or_else(DedupStats::default, |msg_topic| msg_topic.dedup_stats())
+    }
+
+    // Creates a new message channel in the message topic of the given generic type
+    // which optionally joins the given queue group.
+    //
+    // Expired messages are sent back to the message broker as dead letters.
+    pub(crate) fn create_message_channel<M: Message>(
+        self: &Arc<Self>,
+        queue_group: Option<&QueueGroup>,
+    ) -> channel::MessageReceiver {
         let msg_topic = self.get_message_topic(MessageTypeId::of::<M>());
-        msg_topic.create_message_channel()
+        msg_topic.create_message_channel(queue_group, Some(Arc::downgrade(self)))
     }
 
     // Destroys the given message channel.
     pub(crate) fn destroy_message_channel(&self, msg_recv: channel::MessageReceiver) {
-        let msg_topic = self.get_message_topic(msg_recv.message_type_id());
-        let _ = msg_topic.destroy_message_channel(msg_recv);
+        let msg_type_id = msg_recv.message_type_id();
+        if let Some(msg_topic) = self.find_message_topic(msg_type_id) {
+            let _ = msg_topic.destroy_message_channel(msg_recv);
+        }
+        self.release_message_topic(msg_type_id);
+    }
+}
+
+/// Defines what happens with messages which wait in subscriptions
+/// when the message broker is shut down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ShutdownMode {
+    /// Messages are left in subscriptions, so they can still be received.
+    #[default]
+    Drain,
+    /// Messages are removed from subscriptions and returned in [`ShutdownSummary`].
+    Discard,
+}
+
+/// The outcome of shutting the message broker down.
+#[derive(Default)]
+pub struct ShutdownSummary {
+    /// The number of message channels which were closed.
+    pub closed_channels: usize,
+    /// The number of messages which were left in subscriptions to be received.
+    pub pending_messages: usize,
+    /// Messages which were removed from subscriptions, once per subscription.
+    pub discarded_messages: Vec<Arc<dyn Message>>,
+}
+
+// Messages aren't required to implement [`Debug`], so only their type names are shown.
+impl fmt::Debug for ShutdownSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let discarded_messages: Vec<_> = self
+            .discarded_messages
+            .iter()
+            .map(|msg| msg.type_name())
+            .collect();
+
+        f.debug_struct("ShutdownSummary")
+            .field("closed_channels", &self.closed_channels)
+            .field("pending_messages", &self.pending_messages)
+            .field("discarded_messages", &discarded_messages)
+            .finish()
     }
 }
 
 pub struct DefaultMessageBroker {
     msg_topics_map: Mutex<HashMap<MessageTypeId, Arc<MessageTopic>>>,
+    next_seq: AtomicU64,
+    is_shut_down: AtomicBool,
+    clock: Arc<dyn Clock>,
 }
 
 impl DefaultMessageBroker {
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Creates a new [`DefaultMessageBroker`] whose topics expire messages and
+    /// forget the ids in their dedup windows according to the given [`Clock`].
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             msg_topics_map: Mutex::new(HashMap::new()),
+            next_seq: AtomicU64::new(0),
+            is_shut_down: AtomicBool::new(false),
+            clock,
         }
     }
+
+    /// Returns the number of message topics kept by the broker.
+    pub fn topic_count(&self) -> usize {
+        util::lock(&self.msg_topics_map).len()
+    }
+
+    /// Removes all message topics which are no longer needed and returns how many
+    /// of them were removed.
+    ///
+    /// A topic is removed if it has no subscriptions or callbacks, uses the default
+    /// [`DeliveryMode`] and isn't held by anyone else, e.g. by a [`TypedPublisher`].
+    /// Topics are also removed automatically when their last subscription is unregistered,
+    /// but topics which were held by [`TypedPublisher`]s or callbacks at that moment
+    /// are removed only by this method.
+    pub fn compact(&self) -> usize {
+        let mut msg_topics_map = util::lock(&self.msg_topics_map);
+        let topic_count = msg_topics_map.len();
+        msg_topics_map.retain(|_, msg_topic| !Self::is_reclaimable(msg_topic));
+
+        topic_count - msg_topics_map.len()
+    }
+
+    // Returns if the topic is empty and only the broker holds it.
+    fn is_reclaimable(msg_topic: &Arc<MessageTopic>) -> bool {
+        Arc::strong_count(msg_topic) == 1 && msg_topic.is_unused()
+    }
+}
+
+impl Default for DefaultMessageBroker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Subscriptions which outlive the broker learn that they won't receive new messages.
+impl Drop for DefaultMessageBroker {
+    fn drop(&mut self) {
+        let _ = self.shutdown(ShutdownMode::Drain);
+    }
 }
 
 impl MessageBroker for DefaultMessageBroker {
     fn get_message_topic(&self, msg_type_id: MessageTypeId) -> Arc<MessageTopic> {
-        let mut msg_topics_map = self
-            .msg_topics_map
-            .lock()
-            .expect("The default message broker is poisoned");
-        msg_topics_map
-            .entry(msg_type_id)
-            .or_insert(Arc::new(MessageTopic::new(msg_type_id)));
+        let mut msg_topics_map = util::lock(&self.msg_topics_map);
+        let msg_topic = msg_topics_map.entry(msg_type_id).or_insert_with(|| {
+            let msg_topic = MessageTopic::with_clock(msg_type_id, Arc::clone(&self.clock));
+            if self.is_shut_down() {
+                msg_topic.close(ShutdownMode::Discard);
+            }
 
-        Arc::clone(msg_topics_map.get(&msg_type_id).unwrap())
+            Arc::new(msg_topic)
+        });
+
+        Arc::clone(msg_topic)
+    }
+
+    fn find_message_topic(&self, msg_type_id: MessageTypeId) -> Option<Arc<MessageTopic>> {
+        util::lock(&self.msg_topics_map).get(&msg_type_id).cloned()
+    }
+
+    fn release_message_topic(&self, msg_type_id: MessageTypeId) {
+        let mut msg_topics_map = util::lock(&self.msg_topics_map);
+        if msg_topics_map
+            .get(&msg_type_id)
+            .is_some_and(Self::is_reclaimable)
+        {
+            msg_topics_map.remove(&msg_type_id);
+        }
+    }
+
+    fn shutdown(&self, mode: ShutdownMode) -> Result<ShutdownSummary, MessageBrokerError> {
+        let msg_topics_map = util::lock(&self.msg_topics_map);
+        self.is_shut_down.store(true, Ordering::SeqCst);
+
+        let mut summary = ShutdownSummary::default();
+        for msg_topic in msg_topics_map.values() {
+            let topic_summary = msg_topic.close(mode);
+            summary.closed_channels += topic_summary.closed_channels;
+            summary.pending_messages += topic_summary.pending_messages;
+            summary
+                .discarded_messages
+                .extend(topic_summary.discarded_messages);
+        }
+
+        Ok(summary)
+    }
+
+    fn is_shut_down(&self) -> bool {
+        self.is_shut_down.load(Ordering::SeqCst)
+    }
+
+    fn next_sequence_number(&self) -> u64 {
+        self.next_seq.fetch_add(1, Ordering::SeqCst)
     }
 }
 
+#[derive(Debug)]
 pub enum MessageBrokerError {
     SubscriptionError(SubscriptionError),
     MessageTopicError(MessageTopicError),
-    Other(Box<dyn std::any::Any>),
+    MessageHandlerError(MessageHandlerError),
+    /// The message broker doesn't support the operation.
+    Unsupported,
+    /// A custom error of a third-party [`MessageBroker`].
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl MessageBrokerError {
+    /// Creates a [`MessageBrokerError::Other`] from the given custom error.
+    pub fn other(err: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        MessageBrokerError::Other(err.into())
+    }
+}
+
+impl fmt::Display for MessageBrokerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageBrokerError::SubscriptionError(_) => write!(f, "subscription failed"),
+            MessageBrokerError::MessageTopicError(_) => {
+                write!(f, "failed to deliver the message")
+            }
+            MessageBrokerError::MessageHandlerError(_) => {
+                write!(f, "failed to handle the message")
+            }
+            MessageBrokerError::Unsupported => {
+                write!(f, "the message broker doesn't support the operation")
+            }
+            MessageBrokerError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for MessageBrokerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MessageBrokerError::SubscriptionError(sub_err) => Some(sub_err),
+            MessageBrokerError::MessageTopicError(msg_topic_err) => Some(msg_topic_err),
+            MessageBrokerError::MessageHandlerError(handler_err) => Some(handler_err),
+            MessageBrokerError::Unsupported => None,
+            MessageBrokerError::Other(err) => err.source(),
+        }
+    }
+}
+
+impl From<SubscriptionError> for MessageBrokerError {
+    fn from(sub_err: SubscriptionError) -> Self {
+        MessageBrokerError::SubscriptionError(sub_err)
+    }
+}
+
+impl From<MessageTopicError> for MessageBrokerError {
+    fn from(msg_topic_err: MessageTopicError) -> Self {
+        MessageBrokerError::MessageTopicError(msg_topic_err)
+    }
+}
+
+impl From<MessageHandlerError> for MessageBrokerError {
+    fn from(handler_err: MessageHandlerError) -> Self {
+        MessageBrokerError::MessageHandlerError(handler_err)
+    }
 }
@@ -0,0 +1,325 @@
+use crate::{channel::*, *};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex, TryLockError, Weak};
+use std::task::Waker;
+
+type BoxedMessageHandler = Box<dyn ErasedMessageHandler + Send>;
+
+/// Defines where callbacks registered with
+/// [`MessageBroker::on_with`](MessageBroker#method.on_with) are invoked.
+#[derive(Clone, Copy, Default)]
+pub enum CallbackDelivery<'d> {
+    /// The callback is invoked inside [`MessageBroker::publish_message`]
+    /// on the publishing thread.
+    #[default]
+    Sync,
+    /// The callback is invoked on a worker thread of the given [`Dispatcher`].
+    Dispatcher(&'d Dispatcher),
+}
+
+// The message handler of a callback together with the way its failures are handled.
+pub(crate) struct CallbackHandler {
+    handler: BoxedMessageHandler,
+    panic_policy: PanicPolicy,
+    // Receives messages which the handler failed to handle as dead letters.
+    msg_broker: Weak<dyn MessageBroker>,
+}
+
+impl CallbackHandler {
+    // Calls the handler with the given message according to the panic policy
+    // and dead-letters the message if the handler fails.
+    fn call(&mut self, msg: Arc<dyn Message>) {
+        let Err(handler_err) = self.panic_policy.call(&mut *self.handler, Arc::clone(&msg)) else {
+            return;
+        };
+
+        if let Some(msg_broker) = self.msg_broker.upgrade() {
+            let dead_letter = DeadLetter::new(msg, DeadLetterReason::HandlerFailed);
+            msg_broker.dead_letter(dead_letter.with_handler_error(handler_err));
+        }
+    }
+}
+
+// A callback which is invoked by the message topic right after a message is sent.
+pub(crate) struct SyncCallback {
+    msg_recv: MessageReceiver,
+    handler: Mutex<CallbackHandler>,
+}
+
+impl SyncCallback {
+    pub(crate) fn new(msg_recv: MessageReceiver, handler: CallbackHandler) -> Self {
+        Self {
+            msg_recv,
+            handler: Mutex::new(handler),
+        }
+    }
+
+    // Passes all pending messages to the handler.
+    //
+    // If the handler is already running on this or another thread, the messages
+    // are left to that invocation, so the handler is never called re-entrantly
+    // and receives messages in the order they were sent.
+    pub(crate) fn run(&self) {
+        loop {
+            let mut handler = match self.handler.try_lock() {
+                Ok(handler) => handler,
+                Err(TryLockError::Poisoned(err)) => err.into_inner(),
+                Err(TryLockError::WouldBlock) => return,
+            };
+            while let Some(msg) = self.msg_recv.recv() {
+                handler.call(msg);
+            }
+            drop(handler);
+
+            // A message could be sent after the last `recv` but before the handler
+            // was unlocked, while its sender saw the handler locked.
+            if self.msg_recv.pending() == 0 {
+                return;
+            }
+        }
+    }
+
+    // Invokes the handler with the given message, waiting until the handler
+    // is released if it is running on another thread.
+    pub(crate) fn call(&self, msg: Arc<dyn Message>) {
+        let mut handler = util::lock(&self.handler);
+        // Messages which were queued before the topic switched to direct delivery.
+        while let Some(msg) = self.msg_recv.recv() {
+            handler.call(msg);
+        }
+        handler.call(msg);
+    }
+}
+
+type DirectDeliveries = VecDeque<(Arc<SyncCallback>, Arc<dyn Message>)>;
+
+thread_local! {
+    // Direct deliveries which were made by callbacks running on this thread and wait
+    // for the outermost callback to return, `None` if no callback is running.
+    static DEFERRED_DELIVERIES: RefCell<Option<DirectDeliveries>> = const { RefCell::new(None) };
+}
+
+// Clears the deferred deliveries of this thread when the outermost delivery ends,
+// even if a callback panics.
+struct DirectDeliveryScope;
+
+impl Drop for DirectDeliveryScope {
+    fn drop(&mut self) {
+        DEFERRED_DELIVERIES.with(|deferred| *deferred.borrow_mut() = None);
+    }
+}
+
+// Invokes the given callbacks with the message on this thread.
+//
+// If a callback is already running on this thread, the message is delivered
+// after it returns.
+pub(crate) fn deliver_direct(callbacks: Vec<Arc<SyncCallback>>, msg: Arc<dyn Message>) {
+    let mut deliveries: DirectDeliveries = callbacks
+        .into_iter()
+        .map(|callback| (callback, Arc::clone(&msg)))
+        .collect();
+
+    let is_nested = DEFERRED_DELIVERIES.with(|deferred| {
+        let mut deferred = deferred.borrow_mut();
+        match deferred.as_mut() {
+            Some(deferred) => {
+                deferred.append(&mut deliveries);
+                true
+            }
+            None => {
+                *deferred = Some(VecDeque::new());
+                false
+            }
+        }
+    });
+    if is_nested {
+        return;
+    }
+
+    let _scope = DirectDeliveryScope;
+    while !deliveries.is_empty() {
+        deliveries
+            .drain(..)
+            .for_each(|(callback, msg)| callback.call(msg));
+        deliveries =
+            DEFERRED_DELIVERIES.with(|deferred| mem::take(deferred.borrow_mut().as_mut().unwrap()));
+    }
+}
+
+// A subscriber which invokes a callback on a dispatcher thread.
+struct CallbackSubscriber {
+    msg_topic: Arc<MessageTopic>,
+    msg_recv: Option<MessageReceiver>,
+    handler: CallbackHandler,
+}
+
+impl Subscriber for CallbackSubscriber {
+    // The callback is registered in the message topic when it is created.
+    fn subscribe(&mut self, _msg_broker: Arc<dyn MessageBroker>) {}
+
+    // The callback is unregistered when it is dropped.
+    fn unsubscribe(&mut self) {}
+
+    fn activate(&self) {
+        if let Some(ref msg_recv) = self.msg_recv {
+            msg_recv.set_active(true);
+        }
+    }
+
+    fn deactivate(&self) {
+        if let Some(ref msg_recv) = self.msg_recv {
+            msg_recv.set_active(false);
+        }
+    }
+
+    fn set_waker(&self, waker: Option<Waker>) {
+        if let Some(ref msg_recv) = self.msg_recv {
+            msg_recv.set_waker(waker);
+        }
+    }
+
+    fn process_messages(&mut self) {
+        self.process_messages_max(usize::MAX);
+    }
+
+    fn process_messages_max(&mut self, max: usize) -> ProcessedMessages {
+        let Some(ref msg_recv) = self.msg_recv else {
+            return ProcessedMessages::default();
+        };
+
+        let mut handled = 0;
+        while handled < max {
+            let Some(msg) = msg_recv.recv() else { break };
+            self.handler.call(msg);
+            handled += 1;
+        }
+
+        ProcessedMessages {
+            handled,
+            has_more: msg_recv.pending() > 0,
+        }
+    }
+}
+
+impl Drop for CallbackSubscriber {
+    fn drop(&mut self) {
+        if let Some(msg_recv) = self.msg_recv.take() {
+            let _ = self.msg_topic.destroy_message_channel(msg_recv);
+        }
+    }
+}
+
+enum CallbackRegistration {
+    Sync {
+        msg_topic: Arc<MessageTopic>,
+        channel_id: MessageChannelId,
+    },
+    Dispatcher {
+        dispatcher: DispatcherHandle,
+        id: SubscriberId,
+    },
+}
+
+/// A guard which keeps the callback registered with [`MessageBroker::on`](MessageBroker#method.on)
+/// until it is dropped.
+///
+/// Dropping the guard of a callback which is invoked on a [`Dispatcher`] waits until
+/// the callback returns, so the guard mustn't be dropped by the callback itself.
+#[must_use = "the callback is unregistered when the guard is dropped"]
+pub struct CallbackGuard {
+    registration: CallbackRegistration,
+}
+
+impl Drop for CallbackGuard {
+    fn drop(&mut self) {
+        match self.registration {
+            CallbackRegistration::Sync {
+                ref msg_topic,
+                channel_id,
+            } => {
+                let _ = msg_topic.remove_callback(channel_id);
+            }
+            CallbackRegistration::Dispatcher { ref dispatcher, id } => {
+                drop(dispatcher.remove(id));
+            }
+        }
+    }
+}
+
+impl dyn MessageBroker {
+    /// Registers a callback which is invoked synchronously with every published message
+    /// of the given type and returns a guard which unregisters it on drop.
+    ///
+    /// See [`CallbackDelivery::Sync`]
+    pub fn on<M, F>(self: &Arc<Self>, f: F) -> CallbackGuard
+    where
+        M: Message,
+        F: FnMut(Arc<M>) + Send + 'static,
+    {
+        self.on_with(CallbackDelivery::Sync, f)
+    }
+
+    /// Registers a callback which is invoked with every published message of the given type
+    /// on the thread defined by `delivery` and returns a guard which unregisters it on drop.
+    ///
+    /// The callback is never invoked by two threads at once. A message which is published
+    /// by the synchronous callback itself is handled after the callback returns.
+    ///
+    /// Panics of the callback are propagated, see
+    /// [`MessageBroker::on_with_policy`](MessageBroker#method.on_with_policy).
+    pub fn on_with<M, F>(self: &Arc<Self>, delivery: CallbackDelivery<'_>, f: F) -> CallbackGuard
+    where
+        M: Message,
+        F: FnMut(Arc<M>) + Send + 'static,
+    {
+        self.on_with_policy(delivery, PanicPolicy::default(), f)
+    }
+
+    /// Registers a callback like [`MessageBroker::on_with`](MessageBroker#method.on_with)
+    /// whose panics are handled according to `panic_policy`.
+    ///
+    /// Messages which the callback fails to handle are sent to the message broker
+    /// as [`DeadLetter`]s with [`DeadLetterReason::HandlerFailed`].
+    pub fn on_with_policy<M, F>(
+        self: &Arc<Self>,
+        delivery: CallbackDelivery<'_>,
+        panic_policy: PanicPolicy,
+        f: F,
+    ) -> CallbackGuard
+    where
+        M: Message,
+        F: FnMut(Arc<M>) + Send + 'static,
+    {
+        let msg_topic = self.get_message_topic(MessageTypeId::of::<M>());
+        let handler = CallbackHandler {
+            handler: Box::new(f.into_message_handler()),
+            panic_policy,
+            msg_broker: Arc::downgrade(self),
+        };
+
+        let registration = match delivery {
+            CallbackDelivery::Sync => CallbackRegistration::Sync {
+                channel_id: msg_topic.add_callback(handler, Some(Arc::downgrade(self))),
+                msg_topic,
+            },
+            CallbackDelivery::Dispatcher(dispatcher) => {
+                let msg_recv = msg_topic.create_message_channel(None, Some(Arc::downgrade(self)));
+                let id = dispatcher.spawn(Box::new(CallbackSubscriber {
+                    msg_topic,
+                    msg_recv: Some(msg_recv),
+                    handler,
+                }));
+
+                CallbackRegistration::Dispatcher {
+                    dispatcher: dispatcher.handle(),
+                    id,
+                }
+            }
+        };
+
+        CallbackGuard { registration }
+    }
+}
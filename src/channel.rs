@@ -1,38 +1,78 @@
 use crate::*;
 
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::Waker;
+use std::time::Instant;
 
 // A unique id associated with a message channel.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub(crate) struct MessageChannelId(usize);
 
 impl MessageChannelId {
-    // Creates a new [`MessageChannelId`] by casting a pointer to [`MessageTypeId`]
+    // Creates a new [`MessageChannelId`] by casting a pointer to the state
     // which is stored in the given [`MessageSender`] to [`usize`].
     //
-    // The pointer to [`MessageChannelId`] is shared between a sender and a receiver,
+    // The state is shared between a sender and a receiver,
     // so [`MessageChannelId`]s obtained from them will be the same.
     pub(crate) fn from_sender(msg_send: &MessageSender) -> Self {
-        Self(msg_send.msg_type_id.as_ref() as *const _ as usize)
+        Self(Arc::as_ptr(&msg_send.state) as usize)
     }
 
-    // Creates a new [`MessageChannelId`] by casting a pointer to [`MessageTypeId`]
+    // Creates a new [`MessageChannelId`] by casting a pointer to the state
     // which is stored in the given [`MessageReceiver`] to [`usize`].
     //
-    // The pointer to [`MessageChannelId`] is shared between a sender and a receiver,
+    // The state is shared between a sender and a receiver,
     // so [`MessageChannelId`]s obtained from them will be the same.
     pub(crate) fn from_receiver(msg_recv: &MessageReceiver) -> Self {
-        Self(msg_recv.msg_type_id.as_ref() as *const _ as usize)
+        Self(Arc::as_ptr(&msg_recv.state) as usize)
     }
 }
 
+// The time after which a message is discarded instead of being received.
+#[derive(Clone, Copy)]
+pub(crate) struct Expiry {
+    pub(crate) deadline: Instant,
+    pub(crate) policy: ExpiryPolicy,
+}
+
+// A message which waits in the channel together with its sequence number.
+struct Envelope {
+    seq: u64,
+    msg: Arc<dyn Message>,
+    expiry: Option<Expiry>,
+}
+
+// Messages which wait in the channel.
+struct MessageQueue {
+    envelopes: VecDeque<Envelope>,
+    // The number of messages which can still be kept in the channel while it is paused,
+    // `None` if the channel isn't paused.
+    pause_capacity: Option<usize>,
+    // The waker which is woken when a message can be received.
+    waker: Option<Waker>,
+    // The message broker which receives expired messages as dead letters.
+    dead_letter_sink: Option<Weak<dyn MessageBroker>>,
+}
+
+// The state which is shared between both halves of the message channel.
+struct MessageChannelState {
+    msg_type_id: MessageTypeId,
+    queue: Mutex<MessageQueue>,
+    is_active: AtomicBool,
+    is_closed: AtomicBool,
+    // The number of messages which expired before they were received.
+    expired: AtomicUsize,
+    // The source of the time which expiry deadlines are compared with.
+    clock: Arc<dyn Clock>,
+}
+
 // The sending-half of the message channel.
 pub(crate) struct MessageSender {
-    msg_type_id: Arc<MessageTypeId>,
-    send: Sender<Arc<dyn Message>>,
-    is_active: Arc<AtomicBool>,
+    state: Arc<MessageChannelState>,
 }
 
 impl MessageSender {
@@ -43,38 +83,98 @@ impl MessageSender {
 
     // Returns the type id of messages which can be sent through this [`MessageSender`].
     pub(crate) fn message_type_id(&self) -> MessageTypeId {
-        *self.msg_type_id
+        self.state.msg_type_id
     }
 
     // Returns if the channel is active.
     pub(crate) fn is_active(&self) -> bool {
-        self.is_active.load(Ordering::SeqCst)
+        self.state.is_active.load(Ordering::SeqCst)
     }
 
     // Makes the channel active or not depending on `is_active`.
     #[allow(dead_code)]
     pub(crate) fn set_active(&self, is_active: bool) {
-        self.is_active.store(is_active, Ordering::SeqCst);
+        self.state.is_active.store(is_active, Ordering::SeqCst);
+    }
+
+    // Returns the number of messages which wait in the channel.
+    pub(crate) fn pending(&self) -> usize {
+        util::lock(&self.state.queue).envelopes.len()
     }
 
-    // Sends the given messages if messages of its types are supported by the channel.
-    pub(crate) fn send(&self, msg: Arc<dyn Message>) -> Result<(), MessageChannelError> {
-        if msg.type_id() == self.message_type_id() {
-            return self
-                .send
-                .send(msg)
-                .map_err(|_| MessageChannelError::MessageNotSent);
+    // Returns if the channel is paused.
+    pub(crate) fn is_paused(&self) -> bool {
+        util::lock(&self.state.queue).pause_capacity.is_some()
+    }
+
+    // Closes the channel, so the receiver knows no more messages will be sent.
+    //
+    // Messages which wait in the channel are discarded and returned if `discard` is `true`,
+    // otherwise they can still be received.
+    pub(crate) fn close(&self, discard: bool) -> Vec<Arc<dyn Message>> {
+        let mut queue = util::lock(&self.state.queue);
+        self.state.is_closed.store(true, Ordering::SeqCst);
+        let discarded = if discard {
+            queue
+                .envelopes
+                .drain(..)
+                .map(|envelope| envelope.msg)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let waker = queue.waker.clone();
+        drop(queue);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+
+        discarded
+    }
+
+    // Sends the given messages with its sequence number and expiry if messages of its types
+    // are supported by the channel.
+    pub(crate) fn send(
+        &self,
+        msg: Arc<dyn Message>,
+        seq: u64,
+        expiry: Option<Expiry>,
+    ) -> Result<(), MessageChannelError> {
+        if msg.type_id() != self.message_type_id() {
+            return Err(MessageChannelError::WrongMessageType {
+                msg_type_name: msg.type_name(),
+            });
+        }
+
+        let mut queue = util::lock(&self.state.queue);
+        if let Some(ref mut capacity) = queue.pause_capacity {
+            if *capacity == 0 {
+                return Err(MessageChannelError::BufferFull {
+                    msg_type_name: msg.type_name(),
+                });
+            }
+            *capacity -= 1;
+        }
+        queue.envelopes.push_back(Envelope { seq, msg, expiry });
+
+        let waker = queue
+            .pause_capacity
+            .is_none()
+            .then(|| queue.waker.clone())
+            .flatten();
+        drop(queue);
+        if let Some(waker) = waker {
+            waker.wake();
         }
 
-        Err(MessageChannelError::WrongMessageType)
+        Ok(())
     }
 }
 
 // The receiving-half of the message channel.
 pub(crate) struct MessageReceiver {
-    msg_type_id: Arc<MessageTypeId>,
-    recv: Receiver<Arc<dyn Message>>,
-    is_active: Arc<AtomicBool>,
+    state: Arc<MessageChannelState>,
 }
 
 impl MessageReceiver {
@@ -85,47 +185,228 @@ impl MessageReceiver {
 
     // Returns the type id of messages which can be received through this [`MessageReceiver`].
     pub(crate) fn message_type_id(&self) -> MessageTypeId {
-        *self.msg_type_id
+        self.state.msg_type_id
     }
 
     // Returns if the channel is active.
     pub(crate) fn is_active(&self) -> bool {
-        self.is_active.load(Ordering::SeqCst)
+        self.state.is_active.load(Ordering::SeqCst)
     }
 
     // Makes the channel active or not depending on `is_active`.
     pub(crate) fn set_active(&self, is_active: bool) {
-        self.is_active.store(is_active, Ordering::SeqCst);
+        self.state.is_active.store(is_active, Ordering::SeqCst);
+    }
+
+    // Returns if the channel is closed, so no more messages will be sent through it.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.state.is_closed.load(Ordering::SeqCst)
+    }
+
+    // Returns if the channel is paused.
+    pub(crate) fn is_paused(&self) -> bool {
+        util::lock(&self.state.queue).pause_capacity.is_some()
+    }
+
+    // Pauses the channel, so messages are kept in it, but can't be received until
+    // it is resumed. If `capacity` is given, messages which are sent after that many
+    // were kept since the channel was paused are rejected.
+    pub(crate) fn pause(&self, capacity: Option<usize>) {
+        util::lock(&self.state.queue).pause_capacity = Some(capacity.unwrap_or(usize::MAX));
+    }
+
+    // Resumes the channel, so messages which were kept in it can be received.
+    pub(crate) fn resume(&self) {
+        let mut queue = util::lock(&self.state.queue);
+        queue.pause_capacity = None;
+
+        let waker = (!queue.envelopes.is_empty())
+            .then(|| queue.waker.clone())
+            .flatten();
+        drop(queue);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    // Sets the waker which is woken when a message can be received.
+    pub(crate) fn set_waker(&self, waker: Option<Waker>) {
+        util::lock(&self.state.queue).waker = waker;
+    }
+
+    // Returns the number of messages which expired before they were received.
+    pub(crate) fn expired(&self) -> usize {
+        self.state.expired.load(Ordering::SeqCst)
+    }
+
+    // Removes expired messages from the front of the queue and counts them.
+    //
+    // Returns the messages which must be passed to the dead-letter sink together with it.
+    fn remove_expired(&self, queue: &mut MessageQueue) -> ExpiredMessages {
+        let now = self.state.clock.now();
+        let mut expired = ExpiredMessages {
+            msgs: Vec::new(),
+            dead_letter_sink: None,
+        };
+        while let Some(envelope) = queue.envelopes.front() {
+            let Some(expiry) = envelope.expiry.filter(|expiry| expiry.deadline <= now) else {
+                break;
+            };
+
+            let envelope = queue.envelopes.pop_front().unwrap();
+            self.state.expired.fetch_add(1, Ordering::SeqCst);
+            if expiry.policy == ExpiryPolicy::DeadLetter {
+                expired.msgs.push(envelope.msg);
+            }
+        }
+        if !expired.msgs.is_empty() {
+            expired.dead_letter_sink = queue.dead_letter_sink.clone();
+        }
+
+        expired
+    }
+
+    // Returns the number of messages which can be received.
+    //
+    // Messages which are kept in the paused channel aren't counted.
+    pub(crate) fn pending(&self) -> usize {
+        let queue = util::lock(&self.state.queue);
+        if queue.pause_capacity.is_some() {
+            return 0;
+        }
+
+        queue.envelopes.len()
     }
 
-    // Receives the message if there is any.
+    // Returns the sequence number of the message which will be received next.
+    //
+    // Expired messages are skipped.
+    pub(crate) fn peek_sequence_number(&self) -> Option<u64> {
+        let mut queue = util::lock(&self.state.queue);
+        if queue.pause_capacity.is_some() {
+            return None;
+        }
+
+        let expired = self.remove_expired(&mut queue);
+        let seq = queue.envelopes.front().map(|envelope| envelope.seq);
+        drop(queue);
+        expired.dead_letter();
+
+        seq
+    }
+
+    // Receives the message if there is any and the channel isn't paused.
+    //
+    // Expired messages are skipped.
     pub(crate) fn recv(&self) -> Option<Arc<dyn Message>> {
-        self.recv.try_recv().ok()
+        self.recv_if(|_| true)
+    }
+
+    // Receives the message like [`MessageReceiver::recv`] only if it has the given
+    // sequence number, so another receiver can't take it after it was peeked.
+    pub(crate) fn recv_sequenced(&self, seq: u64) -> Option<Arc<dyn Message>> {
+        self.recv_if(|next_seq| next_seq == seq)
+    }
+
+    fn recv_if(&self, accept: impl Fn(u64) -> bool) -> Option<Arc<dyn Message>> {
+        let mut queue = util::lock(&self.state.queue);
+        if queue.pause_capacity.is_some() {
+            return None;
+        }
+
+        let expired = self.remove_expired(&mut queue);
+        let msg = match queue.envelopes.front() {
+            Some(envelope) if accept(envelope.seq) => queue.envelopes.pop_front(),
+            _ => None,
+        }
+        .map(|envelope| envelope.msg);
+        drop(queue);
+        expired.dead_letter();
+
+        msg
+    }
+}
+
+// Messages which expired before they were received.
+struct ExpiredMessages {
+    msgs: Vec<Arc<dyn Message>>,
+    dead_letter_sink: Option<Weak<dyn MessageBroker>>,
+}
+
+impl ExpiredMessages {
+    // Passes the messages to the dead-letter sink if it is still alive.
+    //
+    // It must be called without holding the lock of the channel, since the dead letters
+    // may be sent to it.
+    fn dead_letter(self) {
+        let Some(msg_broker) = self
+            .dead_letter_sink
+            .and_then(|msg_broker| msg_broker.upgrade())
+        else {
+            return;
+        };
+
+        self.msgs.into_iter().for_each(|msg| {
+            msg_broker.dead_letter(DeadLetter::new(msg, DeadLetterReason::Expired));
+        });
     }
 }
 
 // Creates a new message channel which can be used for sending messages with the given type id.
-pub(crate) fn message_channel_new(msg_type_id: MessageTypeId) -> (MessageSender, MessageReceiver) {
-    let msg_type_id = Arc::new(msg_type_id);
-    let (send, recv) = mpsc::channel();
-    let is_active = Arc::new(AtomicBool::new(true));
+//
+// Expired messages are passed to `dead_letter_sink` as dead letters
+// if their topic uses [`ExpiryPolicy::DeadLetter`]. Messages expire according to `clock`.
+pub(crate) fn message_channel_new(
+    msg_type_id: MessageTypeId,
+    dead_letter_sink: Option<Weak<dyn MessageBroker>>,
+    clock: Arc<dyn Clock>,
+) -> (MessageSender, MessageReceiver) {
+    let state = Arc::new(MessageChannelState {
+        msg_type_id,
+        queue: Mutex::new(MessageQueue {
+            envelopes: VecDeque::new(),
+            pause_capacity: None,
+            waker: None,
+            dead_letter_sink,
+        }),
+        is_active: AtomicBool::new(true),
+        is_closed: AtomicBool::new(false),
+        expired: AtomicUsize::new(0),
+        clock,
+    });
 
     let msg_send = MessageSender {
-        msg_type_id: Arc::clone(&msg_type_id),
-        send,
-        is_active: Arc::clone(&is_active),
-    };
-    let msg_recv = MessageReceiver {
-        msg_type_id: Arc::clone(&msg_type_id),
-        recv,
-        is_active,
+        state: Arc::clone(&state),
     };
+    let msg_recv = MessageReceiver { state };
 
     (msg_send, msg_recv)
 }
 
 #[derive(Debug)]
 pub enum MessageChannelError {
-    WrongMessageType,
-    MessageNotSent,
+    /// The channel doesn't accept messages of the given type.
+    WrongMessageType { msg_type_name: &'static str },
+    /// The channel is paused and can't keep any more messages, so the message
+    /// of the given type was rejected.
+    BufferFull { msg_type_name: &'static str },
 }
+
+impl fmt::Display for MessageChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageChannelError::WrongMessageType { msg_type_name } => write!(
+                f,
+                "the message channel doesn't accept messages of type `{}`",
+                msg_type_name
+            ),
+            MessageChannelError::BufferFull { msg_type_name } => write!(
+                f,
+                "the paused message channel is full and rejected a message of type `{}`",
+                msg_type_name
+            ),
+        }
+    }
+}
+
+impl Error for MessageChannelError {}
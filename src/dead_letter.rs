@@ -0,0 +1,69 @@
+use crate::*;
+
+use std::sync::Arc;
+
+/// The reason why a message became a [`DeadLetter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeadLetterReason {
+    /// The message was published while nobody was subscribed to its type.
+    NoSubscribers,
+    /// The message was rejected because its type doesn't match the type of the message topic.
+    WrongMessageType,
+    /// The message handler failed to handle the message.
+    HandlerFailed,
+    /// The message didn't fit into the buffer of a paused subscription.
+    BufferFull,
+    /// The time to live of the message ran out before it was received
+    /// (see [`ExpiryPolicy::DeadLetter`]).
+    Expired,
+}
+
+/// A message which couldn't be delivered or handled.
+///
+/// Dead letters are published by the message broker like any other message,
+/// so they can be drained and inspected with a [`Subscription<DeadLetter>`].
+pub struct DeadLetter {
+    msg: Arc<dyn Message>,
+    reason: DeadLetterReason,
+    handler_err: Option<MessageHandlerError>,
+}
+
+impl DeadLetter {
+    /// Creates a new [`DeadLetter`] from the given message and the reason why it wasn't delivered.
+    pub fn new(msg: Arc<dyn Message>, reason: DeadLetterReason) -> Self {
+        Self {
+            msg,
+            reason,
+            handler_err: None,
+        }
+    }
+
+    /// Attaches the error returned by the message handler which failed to handle the message.
+    pub fn with_handler_error(mut self, handler_err: MessageHandlerError) -> Self {
+        self.handler_err = Some(handler_err);
+
+        self
+    }
+
+    /// Returns the message which wasn't delivered.
+    pub fn message(&self) -> Arc<dyn Message> {
+        Arc::clone(&self.msg)
+    }
+
+    /// Returns the reason why the message wasn't delivered.
+    pub fn reason(&self) -> DeadLetterReason {
+        self.reason
+    }
+
+    /// Returns the error returned by the message handler if the message wasn't handled.
+    pub fn handler_error(&self) -> Option<&MessageHandlerError> {
+        self.handler_err.as_ref()
+    }
+
+    /// Publishes the message which wasn't delivered once again in the given message broker.
+    pub fn republish(&self, msg_broker: &dyn MessageBroker) -> Result<(), MessageBrokerError> {
+        msg_broker.publish_message(self.message())
+    }
+}
+
+impl Message for DeadLetter {}
@@ -0,0 +1,380 @@
+use crate::*;
+
+use std::collections::{HashMap, VecDeque};
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::task::{Wake, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A subscriber which can be driven by a [`Dispatcher`].
+pub type BoxedSubscriber = Box<dyn Subscriber + Send>;
+
+/// A unique id of a subscriber which is driven by a [`Dispatcher`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubscriberId(u64);
+
+// A subscriber which is owned by the dispatcher.
+struct SubscriberSlot {
+    // `None` while the subscriber is processing messages on a worker thread.
+    subscriber: Option<BoxedSubscriber>,
+    // Whether the subscriber waits in the ready queue.
+    is_scheduled: bool,
+    // Whether the subscriber was woken while it was processing messages.
+    is_woken: bool,
+}
+
+struct DispatcherState {
+    slots: HashMap<SubscriberId, SubscriberSlot>,
+    ready: VecDeque<SubscriberId>,
+    next_id: u64,
+    last_poll: Instant,
+    is_shut_down: bool,
+}
+
+impl DispatcherState {
+    // Puts the subscriber with the given id into the ready queue, or makes it run
+    // once again if it is processing messages now.
+    fn schedule(&mut self, id: SubscriberId) {
+        let Some(slot) = self.slots.get_mut(&id) else {
+            return;
+        };
+
+        if slot.subscriber.is_none() {
+            slot.is_woken = true;
+        } else if !slot.is_scheduled {
+            slot.is_scheduled = true;
+            self.ready.push_back(id);
+        }
+    }
+
+    // Puts all subscribers which aren't processing messages into the ready queue.
+    fn schedule_all(&mut self) {
+        for (&id, slot) in self.slots.iter_mut() {
+            if slot.subscriber.is_some() && !slot.is_scheduled {
+                slot.is_scheduled = true;
+                self.ready.push_back(id);
+            }
+        }
+    }
+
+    // Takes the next ready subscriber out of its slot.
+    fn next_ready(&mut self) -> Option<(SubscriberId, BoxedSubscriber)> {
+        while let Some(id) = self.ready.pop_front() {
+            let Some(slot) = self.slots.get_mut(&id) else {
+                continue;
+            };
+            slot.is_scheduled = false;
+            if let Some(subscriber) = slot.subscriber.take() {
+                return Some((id, subscriber));
+            }
+        }
+
+        None
+    }
+}
+
+// The state which is shared between the dispatcher, its worker threads and wakers.
+struct DispatcherShared {
+    state: Mutex<DispatcherState>,
+    // Notified when a subscriber becomes ready or returns to its slot.
+    cvar: Condvar,
+    poll_interval: Duration,
+}
+
+impl DispatcherShared {
+    fn lock(&self) -> MutexGuard<'_, DispatcherState> {
+        util::lock(&self.state)
+    }
+
+    fn wake(&self, id: SubscriberId) {
+        self.lock().schedule(id);
+        self.cvar.notify_all();
+    }
+
+    // Takes the subscriber with the given id out of its slot after it stops running.
+    fn remove(&self, id: SubscriberId) -> Option<BoxedSubscriber> {
+        let mut state = self.lock();
+        while state
+            .slots
+            .get(&id)
+            .is_some_and(|slot| slot.subscriber.is_none())
+        {
+            state = self.cvar.wait(state).unwrap_or_else(|err| err.into_inner());
+        }
+
+        let subscriber = state.slots.remove(&id)?.subscriber?;
+        drop(state);
+        subscriber.set_waker(None);
+
+        Some(subscriber)
+    }
+
+    // Runs ready subscribers until the dispatcher is shut down.
+    fn run_worker(&self) {
+        let mut state = self.lock();
+        loop {
+            if state.is_shut_down {
+                return;
+            }
+
+            let Some((id, mut subscriber)) = state.next_ready() else {
+                let elapsed = state.last_poll.elapsed();
+                if elapsed >= self.poll_interval {
+                    state.last_poll = Instant::now();
+                    state.schedule_all();
+                    continue;
+                }
+
+                state = self
+                    .cvar
+                    .wait_timeout(state, self.poll_interval - elapsed)
+                    .unwrap_or_else(|err| err.into_inner())
+                    .0;
+                continue;
+            };
+            drop(state);
+
+            // A panicking subscriber must not bring the worker thread down.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| subscriber.process_messages()));
+
+            state = self.lock();
+            let slot = state
+                .slots
+                .get_mut(&id)
+                .expect("subscribers can't be removed while they are running");
+            slot.subscriber = Some(subscriber);
+            if slot.is_woken {
+                slot.is_woken = false;
+                state.schedule(id);
+            }
+            self.cvar.notify_all();
+        }
+    }
+}
+
+// The waker which makes the subscriber with the given id ready.
+struct SubscriberWaker {
+    id: SubscriberId,
+    shared: Weak<DispatcherShared>,
+}
+
+impl Wake for SubscriberWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(shared) = self.shared.upgrade() {
+            shared.wake(self.id);
+        }
+    }
+}
+
+// A weak reference to the dispatcher.
+pub(crate) struct DispatcherHandle {
+    shared: Weak<DispatcherShared>,
+}
+
+impl DispatcherHandle {
+    // Takes the subscriber with the given id back from the dispatcher
+    // if the dispatcher is still alive.
+    pub(crate) fn remove(&self, id: SubscriberId) -> Option<BoxedSubscriber> {
+        self.shared.upgrade()?.remove(id)
+    }
+}
+
+/// A builder which configures a [`Dispatcher`].
+#[derive(Debug, Clone)]
+pub struct DispatcherBuilder {
+    threads: usize,
+    poll_interval: Duration,
+}
+
+impl DispatcherBuilder {
+    /// Creates a new [`DispatcherBuilder`] with a thread per available CPU
+    /// and a poll interval of 10 milliseconds.
+    pub fn new() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            poll_interval: Duration::from_millis(10),
+        }
+    }
+
+    /// Sets the number of worker threads. At least one thread is always spawned.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+
+        self
+    }
+
+    /// Sets how often subscribers are run even if they weren't woken.
+    ///
+    /// Polling is needed only for subscribers which don't override
+    /// [`Subscriber::set_waker`].
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+
+        self
+    }
+
+    /// Spawns the worker threads and returns the [`Dispatcher`] which owns them.
+    pub fn build(self) -> Dispatcher {
+        let shared = Arc::new(DispatcherShared {
+            state: Mutex::new(DispatcherState {
+                slots: HashMap::new(),
+                ready: VecDeque::new(),
+                next_id: 0,
+                last_poll: Instant::now(),
+                is_shut_down: false,
+            }),
+            cvar: Condvar::new(),
+            poll_interval: self.poll_interval,
+        });
+
+        let workers = (0..self.threads)
+            .map(|idx| {
+                let shared = Arc::clone(&shared);
+                thread::Builder::new()
+                    .name(format!("lps-dispatcher-{}", idx))
+                    .spawn(move || shared.run_worker())
+                    .expect("failed to spawn a dispatcher thread")
+            })
+            .collect();
+
+        Dispatcher { shared, workers }
+    }
+}
+
+impl Default for DispatcherBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A thread pool which owns [`Subscriber`]s and makes them process their messages.
+///
+/// A subscriber is run on one of the worker threads whenever any of its [`Subscription`]s
+/// receives a message, as long as it forwards the waker given to [`Subscriber::set_waker`]
+/// to them. Other subscribers are run periodically (see [`DispatcherBuilder::poll_interval`]).
+/// The same subscriber never runs on two threads at once.
+///
+/// Dropping the dispatcher shuts it down (see [`Dispatcher::shutdown`]).
+pub struct Dispatcher {
+    shared: Arc<DispatcherShared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Dispatcher {
+    /// Creates a new [`Dispatcher`] with the default configuration.
+    ///
+    /// See [`DispatcherBuilder::new`]
+    pub fn new() -> Self {
+        DispatcherBuilder::new().build()
+    }
+
+    /// Returns a [`DispatcherBuilder`] which configures a new [`Dispatcher`].
+    pub fn builder() -> DispatcherBuilder {
+        DispatcherBuilder::new()
+    }
+
+    /// Passes the ownership of the given subscriber to the dispatcher
+    /// and returns its id.
+    ///
+    /// The subscriber is run right away to process messages which are already pending.
+    pub fn spawn(&self, subscriber: BoxedSubscriber) -> SubscriberId {
+        let mut state = self.shared.lock();
+        let id = SubscriberId(state.next_id);
+        state.next_id += 1;
+
+        subscriber.set_waker(Some(Waker::from(Arc::new(SubscriberWaker {
+            id,
+            shared: Arc::downgrade(&self.shared),
+        }))));
+        state.slots.insert(
+            id,
+            SubscriberSlot {
+                subscriber: Some(subscriber),
+                is_scheduled: false,
+                is_woken: false,
+            },
+        );
+        state.schedule(id);
+        drop(state);
+        self.shared.cvar.notify_all();
+
+        id
+    }
+
+    /// Takes the subscriber with the given id back from the dispatcher.
+    ///
+    /// Waits until the subscriber finishes processing messages if it is running,
+    /// so it mustn't be called by the subscriber itself.
+    pub fn remove(&self, id: SubscriberId) -> Option<BoxedSubscriber> {
+        self.shared.remove(id)
+    }
+
+    // Returns a handle which doesn't keep the dispatcher alive.
+    pub(crate) fn handle(&self) -> DispatcherHandle {
+        DispatcherHandle {
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+
+    /// Returns the number of subscribers owned by the dispatcher.
+    pub fn len(&self) -> usize {
+        self.shared.lock().slots.len()
+    }
+
+    /// Returns `true` if the dispatcher doesn't own any subscribers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stops the worker threads after they finish processing messages, then makes
+    /// every subscriber process its pending messages for the last time on the current
+    /// thread and returns all subscribers.
+    ///
+    /// Messages which subscribers publish to each other while being drained
+    /// may remain pending.
+    pub fn shutdown(mut self) -> Vec<BoxedSubscriber> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Vec<BoxedSubscriber> {
+        self.shared.lock().is_shut_down = true;
+        self.shared.cvar.notify_all();
+        self.workers.drain(..).for_each(|worker| {
+            let _ = worker.join();
+        });
+
+        let mut slots: Vec<_> = self.shared.lock().slots.drain().collect();
+        slots.sort_by_key(|&(id, _)| id);
+
+        slots
+            .into_iter()
+            .filter_map(|(_, slot)| slot.subscriber)
+            .map(|mut subscriber| {
+                subscriber.set_waker(None);
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| subscriber.process_messages()));
+
+                subscriber
+            })
+            .collect()
+    }
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        if !self.workers.is_empty() {
+            self.stop();
+        }
+    }
+}
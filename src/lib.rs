@@ -2,7 +2,9 @@
 //! 
 //! # Example
 //! 
-//! ```
+// The example needs the default `std` feature.
+#![cfg_attr(feature = "std", doc = "```")]
+#![cfg_attr(not(feature = "std"), doc = "```ignore")]
 //! use lps::*;
 //! 
 //! use std::sync::Arc;
@@ -69,6 +71,12 @@
 //!             })
 //!             .run();
 //!     }
+//! 
+//!     fn process_messages_max(&mut self, max: usize) -> ProcessedMessages {
+//!         self.sub.process_messages_max(max, |msg| {
+//!             println!("The subscriber received a message: {:?}", msg);
+//!         })
+//!     }
 //! }
 //! 
 //! let msg_broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
@@ -85,19 +93,76 @@
 //! s.process_messages();
 //! 
 //! ```
+//! 
+//! # `no_std` support
+//! 
+//! Without the default `std` feature the crate is `#![no_std]` and needs only `alloc`.
+//! It provides [`Message`], the message handler traits, [`MessageIterator`]
+//! and [`SpinMessageBroker`], which is built on spin locks and fixed-size ring buffers.
+
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod ambient;
+#[cfg(feature = "std")]
 mod broker;
+#[cfg(feature = "std")]
+mod callback;
+#[cfg(feature = "std")]
 mod channel;
+#[cfg(feature = "std")]
+mod dead_letter;
+#[cfg(feature = "std")]
+mod dispatcher;
+#[cfg(feature = "std")]
+mod local;
 mod message;
+#[cfg(feature = "std")]
 mod publisher;
+mod ring;
+#[cfg(feature = "std")]
+mod scheduler;
+mod spin;
+mod spin_broker;
+#[cfg(feature = "std")]
 mod subscriber;
+#[cfg(feature = "std")]
 mod subscription;
+#[cfg(feature = "std")]
 mod topic;
 mod util;
 
+#[cfg(feature = "std")]
+pub use ambient::*;
+#[cfg(feature = "std")]
 pub use broker::*;
+#[cfg(feature = "std")]
+pub use callback::*;
+#[cfg(feature = "std")]
+pub use channel::MessageChannelError;
+#[cfg(feature = "std")]
+pub use dead_letter::*;
+#[cfg(feature = "std")]
+pub use dispatcher::*;
+#[cfg(feature = "std")]
+pub use local::*;
 pub use message::*;
+#[cfg(feature = "std")]
 pub use publisher::*;
+#[cfg(feature = "std")]
+pub use scheduler::*;
+pub use spin_broker::*;
+#[cfg(feature = "std")]
 pub use subscriber::*;
+#[cfg(feature = "std")]
 pub use subscription::*;
+#[cfg(feature = "std")]
 pub use topic::*;
+
+// The items used by the exported macros, which aren't a part of the public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::util::AsAny;
+}
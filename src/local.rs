@@ -0,0 +1,384 @@
+use crate::*;
+
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::iter::Chain;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// A type which is used for communicating between publishers and subscribers
+/// on the same thread.
+///
+/// Unlike [`Message`], it doesn't require `Send + Sync`, so it can contain [`Rc`]s
+/// and [`RefCell`]s.
+pub trait LocalMessage: util::AsAny + 'static {
+    /// Returns the type id of the message.
+    fn type_id(&self) -> MessageTypeId {
+        MessageTypeId(self.as_any_ref().type_id())
+    }
+
+    /// Returns the name of the message type.
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+// Returns the [`MessageTypeId`] of the given local message type.
+fn local_message_type_id<M: LocalMessage>() -> MessageTypeId {
+    MessageTypeId(TypeId::of::<M>())
+}
+
+// Downcasts the local message to the given type, returning it back if its type is different.
+fn downcast_local_message<M: LocalMessage>(
+    msg: Rc<dyn LocalMessage>,
+) -> Result<Rc<M>, Rc<dyn LocalMessage>> {
+    if (*msg).type_id() != local_message_type_id::<M>() {
+        return Err(msg);
+    }
+
+    Ok(msg.as_any_rc().downcast().unwrap())
+}
+
+// A queue of local messages which are waiting to be received by a subscription.
+struct LocalMessageChannel {
+    msgs: RefCell<VecDeque<Rc<dyn LocalMessage>>>,
+    is_active: Cell<bool>,
+}
+
+/// A message broker which delivers [`LocalMessage`]s within one thread.
+///
+/// It can't be shared between threads, so it is used through [`Rc`].
+#[derive(Default)]
+pub struct LocalMessageBroker {
+    msg_topics_map: RefCell<HashMap<MessageTypeId, Vec<Rc<LocalMessageChannel>>>>,
+}
+
+impl LocalMessageBroker {
+    pub fn new() -> Self {
+        Self {
+            msg_topics_map: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Sends the given message to all active subscriptions to messages of its type.
+    ///
+    /// Messages which nobody listens for are dropped.
+    pub fn publish_message(&self, msg: Rc<dyn LocalMessage>) {
+        let msg_topics_map = self.msg_topics_map.borrow();
+        let Some(channels) = msg_topics_map.get(&(*msg).type_id()) else {
+            return;
+        };
+
+        channels
+            .iter()
+            .filter(|channel| channel.is_active.get())
+            .for_each(|channel| channel.msgs.borrow_mut().push_back(Rc::clone(&msg)));
+    }
+
+    /// Wraps the given message into [`Rc`] and publishes it.
+    pub fn publish_value<M: LocalMessage>(&self, msg: M) {
+        self.publish_message(Rc::new(msg));
+    }
+
+    fn create_channel(&self, msg_type_id: MessageTypeId) -> Rc<LocalMessageChannel> {
+        let channel = Rc::new(LocalMessageChannel {
+            msgs: RefCell::new(VecDeque::new()),
+            is_active: Cell::new(true),
+        });
+        self.msg_topics_map
+            .borrow_mut()
+            .entry(msg_type_id)
+            .or_default()
+            .push(Rc::clone(&channel));
+
+        channel
+    }
+
+    fn destroy_channel(&self, msg_type_id: MessageTypeId, channel: &Rc<LocalMessageChannel>) {
+        let mut msg_topics_map = self.msg_topics_map.borrow_mut();
+        let Some(channels) = msg_topics_map.get_mut(&msg_type_id) else {
+            return;
+        };
+
+        channels.retain(|other| !Rc::ptr_eq(other, channel));
+        if channels.is_empty() {
+            msg_topics_map.remove(&msg_type_id);
+        }
+    }
+}
+
+/// A type which is used for receiving [`LocalMessage`]s of a specific type
+/// from the [`LocalMessageBroker`].
+///
+/// See [`Subscription`]
+pub struct LocalSubscription<M: LocalMessage> {
+    msg_broker: Option<Rc<LocalMessageBroker>>,
+    channel: Option<Rc<LocalMessageChannel>>,
+    _msg_type: PhantomData<M>,
+}
+
+impl<M: LocalMessage> LocalSubscription<M> {
+    /// Creates a new [`LocalSubscription`] which is not registered in any message broker
+    /// and therefore can't be used for receiving messages.
+    pub fn unregistered() -> Self {
+        Self {
+            msg_broker: None,
+            channel: None,
+            _msg_type: PhantomData,
+        }
+    }
+
+    /// Creates a new [`LocalSubscription`] which is registered in the given message broker.
+    pub fn new(msg_broker: Rc<LocalMessageBroker>) -> Self {
+        let mut sub = Self::unregistered();
+        let _ = sub.register(msg_broker);
+
+        sub
+    }
+
+    /// Returns a message broker which sends messages to this subscription.
+    pub fn message_broker(&self) -> Option<Rc<LocalMessageBroker>> {
+        self.msg_broker.clone()
+    }
+
+    /// Returns if the subscription is registered.
+    pub fn is_registered(&self) -> bool {
+        self.msg_broker.is_some() && self.channel.is_some()
+    }
+
+    /// Returns if the subscription is active.
+    pub fn is_active(&self) -> bool {
+        self.channel
+            .as_ref()
+            .is_some_and(|channel| channel.is_active.get())
+    }
+
+    /// Registers the subscription in the given message broker.
+    pub fn register(
+        &mut self,
+        msg_broker: Rc<LocalMessageBroker>,
+    ) -> Result<(), SubscriptionError> {
+        if self.is_registered() {
+            return Err(SubscriptionError::AlreadyRegistered);
+        }
+
+        self.channel = Some(msg_broker.create_channel(local_message_type_id::<M>()));
+        self.msg_broker = Some(msg_broker);
+
+        Ok(())
+    }
+
+    /// Unregisters the subscription in the given message broker.
+    pub fn unregister(&mut self) -> Result<(), SubscriptionError> {
+        let Some(msg_broker) = self.msg_broker.take() else {
+            return Err(SubscriptionError::NotRegistered);
+        };
+        let Some(channel) = self.channel.take() else {
+            return Err(SubscriptionError::NotRegistered);
+        };
+
+        msg_broker.destroy_channel(local_message_type_id::<M>(), &channel);
+
+        Ok(())
+    }
+
+    /// Activates the subscription if it was deactivated before.
+    pub fn activate(&self) -> Result<(), SubscriptionError> {
+        let Some(ref channel) = self.channel else {
+            return Err(SubscriptionError::NotRegistered);
+        };
+        channel.is_active.set(true);
+
+        Ok(())
+    }
+
+    /// Dectivates the subscription, in other words temporary makes it stop receiving messages.
+    ///
+    /// Messages which are published while the subscription is deactivated are dropped.
+    pub fn deactivate(&self) -> Result<(), SubscriptionError> {
+        let Some(ref channel) = self.channel else {
+            return Err(SubscriptionError::NotRegistered);
+        };
+        channel.is_active.set(false);
+
+        Ok(())
+    }
+
+    /// Receives one message if there is any.
+    pub fn recv_message(&self) -> Option<Rc<M>> {
+        self.recv_local_message()
+            .map(|msg| downcast_local_message(msg).ok().unwrap())
+    }
+
+    /// Returns the number of messages which are waiting to be received.
+    pub fn pending_messages(&self) -> usize {
+        self.channel
+            .as_ref()
+            .map_or(0, |channel| channel.msgs.borrow().len())
+    }
+
+    /// Returns an iterator that will attempt to yield all pending messages.
+    pub fn message_iter(&self) -> LocalMessageIter<'_> {
+        LocalMessageIter {
+            recv: Box::new(|| self.recv_local_message()),
+            pending: Box::new(|| self.pending_messages()),
+        }
+    }
+
+    /// Processes all pending messages by calling the given function on each one.
+    ///
+    /// Messages which are published by the function itself are processed too.
+    pub fn process_messages<F: FnMut(Rc<M>)>(&self, mut f: F) {
+        while let Some(msg) = self.recv_message() {
+            f(msg);
+        }
+    }
+
+    // The borrow of the channel ends before the message is returned,
+    // so handlers can publish new messages.
+    fn recv_local_message(&self) -> Option<Rc<dyn LocalMessage>> {
+        self.channel.as_ref()?.msgs.borrow_mut().pop_front()
+    }
+}
+
+impl<M: LocalMessage> Default for LocalSubscription<M> {
+    fn default() -> Self {
+        Self::unregistered()
+    }
+}
+
+impl<M: LocalMessage> Drop for LocalSubscription<M> {
+    fn drop(&mut self) {
+        let _ = self.unregister();
+    }
+}
+
+/// An iterator which yields [`LocalMessage`]s.
+///
+/// See [`MessageIterator`]
+pub trait LocalMessageIterator: Iterator<Item = Rc<dyn LocalMessage>> {
+    /// Takes a message handler and creates an iterator which calls that message handler
+    /// on each received message of the given type.
+    fn handle<M, F>(self, f: F) -> LocalHandleMessage<Self, M, F>
+    where
+        Self: Sized,
+        M: LocalMessage,
+        F: FnMut(Rc<M>),
+    {
+        LocalHandleMessage {
+            iter: self,
+            f,
+            _msg_type: PhantomData,
+        }
+    }
+
+    /// Creates an iterator which yields only messages of the given type.
+    ///
+    /// Messages of other types are received and dropped.
+    fn filter_type<M: LocalMessage>(self) -> LocalFilterType<Self, M>
+    where
+        Self: Sized,
+    {
+        LocalFilterType {
+            iter: self,
+            _msg_type: PhantomData,
+        }
+    }
+
+    /// Runs an iterator.
+    fn run(self)
+    where
+        Self: Sized,
+    {
+        self.for_each(|_| {});
+    }
+}
+
+/// An iterator which yields messages from one [`LocalSubscription`].
+///
+/// This `struct` is created by [`LocalSubscription::message_iter`].
+pub struct LocalMessageIter<'s> {
+    recv: Box<dyn Fn() -> Option<Rc<dyn LocalMessage>> + 's>,
+    pending: Box<dyn Fn() -> usize + 's>,
+}
+
+impl Iterator for LocalMessageIter<'_> {
+    type Item = Rc<dyn LocalMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        (self.recv)()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        ((self.pending)(), None)
+    }
+}
+
+impl LocalMessageIterator for LocalMessageIter<'_> {}
+
+impl<A, B> LocalMessageIterator for Chain<A, B>
+where
+    A: LocalMessageIterator,
+    B: LocalMessageIterator,
+{
+}
+
+/// A local message iterator which handles messages of type `M` with `f`.
+///
+/// This `struct` is created by [`LocalMessageIterator::handle`].
+pub struct LocalHandleMessage<I, M, F> {
+    iter: I,
+    f: F,
+    _msg_type: PhantomData<fn(Rc<M>)>,
+}
+
+impl<I, M, F> Iterator for LocalHandleMessage<I, M, F>
+where
+    I: Iterator<Item = Rc<dyn LocalMessage>>,
+    M: LocalMessage,
+    F: FnMut(Rc<M>),
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let msg = self.iter.next()?;
+        if let Ok(typed_msg) = downcast_local_message(Rc::clone(&msg)) {
+            (self.f)(typed_msg);
+        }
+
+        Some(msg)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<I, M, F> LocalMessageIterator for LocalHandleMessage<I, M, F>
+where
+    I: Iterator<Item = Rc<dyn LocalMessage>>,
+    M: LocalMessage,
+    F: FnMut(Rc<M>),
+{
+}
+
+/// An iterator which yields only local messages of type `M`.
+///
+/// This `struct` is created by [`LocalMessageIterator::filter_type`].
+pub struct LocalFilterType<I, M> {
+    iter: I,
+    _msg_type: PhantomData<fn() -> M>,
+}
+
+impl<I: Iterator<Item = Rc<dyn LocalMessage>>, M: LocalMessage> Iterator for LocalFilterType<I, M> {
+    type Item = Rc<M>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find_map(|msg| downcast_local_message(msg).ok())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
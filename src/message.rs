@@ -1,7 +1,15 @@
-use std::any::TypeId;
-use std::iter::Iterator;
-use std::marker::PhantomData;
-use std::sync::Arc;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::TypeId;
+use core::error::Error;
+use core::fmt;
+use core::iter::{Chain, Iterator};
+use core::marker::PhantomData;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::panic::{self, AssertUnwindSafe};
 
 use crate::*;
 
@@ -11,6 +19,28 @@ pub trait Message: util::AsAny + Send + Sync + 'static {
     fn type_id(&self) -> MessageTypeId {
         MessageTypeId(self.as_any_ref().type_id())
     }
+
+    /// Returns the name of the message type.
+    fn type_name(&self) -> &'static str {
+        core::any::type_name::<Self>()
+    }
+
+    /// Returns how long the message stays relevant after it is published.
+    ///
+    /// A message which isn't received in time is skipped by subscriptions.
+    /// It overrides the time to live of the message topic, `None` means the message
+    /// uses the one of its topic.
+    fn ttl(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the id of the logical event which the message represents.
+    ///
+    /// Messages with the same id are treated as duplicates by message topics
+    /// which drop them (see `MessageTopic::set_dedup_window`).
+    fn message_id(&self) -> Option<u64> {
+        None
+    }
 }
 
 /// The type id of the message.
@@ -24,6 +54,17 @@ impl MessageTypeId {
     }
 }
 
+// Downcasts the message to the given type, returning it back if its type is different.
+pub(crate) fn downcast_message<M: Message>(
+    msg: Arc<dyn Message>,
+) -> Result<Arc<M>, Arc<dyn Message>> {
+    if msg.type_id() != MessageTypeId::of::<M>() {
+        return Err(msg);
+    }
+
+    Ok(msg.as_any_arc().downcast().unwrap())
+}
+
 /// A function with erased type for handling messages.
 pub trait ErasedMessageHandler {
     /// Runs the function with the given message.
@@ -42,9 +83,9 @@ pub struct MessageHandler<M: ?Sized, F> {
 impl<M: Message, F: FnMut(Arc<M>)> ErasedMessageHandler for MessageHandler<M, F> {
     fn call(&mut self, msg: Arc<dyn Message>) -> Result<(), MessageHandlerError> {
         (self.f)(
-            msg.as_any_arc()
-                .downcast()
-                .map_err(|_| MessageHandlerError::WrongMessageType)?,
+            downcast_message(msg).map_err(|msg| MessageHandlerError::WrongMessageType {
+                msg_type_name: msg.type_name(),
+            })?,
         );
 
         Ok(())
@@ -135,8 +176,86 @@ impl<M: Message + ?Sized> IntoMessageHandler<M> for Box<dyn ErasedMessageHandler
     }
 }
 
+#[derive(Debug)]
 pub enum MessageHandlerError {
-    WrongMessageType,
+    /// The message handler can't handle messages of the given type.
+    WrongMessageType { msg_type_name: &'static str },
+    /// The message handler panicked while handling a message of the given type.
+    Panicked {
+        msg_type_name: &'static str,
+        panic_message: Option<String>,
+    },
+}
+
+impl fmt::Display for MessageHandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageHandlerError::WrongMessageType { msg_type_name } => write!(
+                f,
+                "the message handler can't handle messages of type `{}`",
+                msg_type_name
+            ),
+            MessageHandlerError::Panicked {
+                msg_type_name,
+                panic_message: Some(panic_message),
+            } => write!(
+                f,
+                "the message handler panicked while handling a message of type `{}`: {}",
+                msg_type_name, panic_message
+            ),
+            MessageHandlerError::Panicked { msg_type_name, .. } => write!(
+                f,
+                "the message handler panicked while handling a message of type `{}`",
+                msg_type_name
+            ),
+        }
+    }
+}
+
+impl Error for MessageHandlerError {}
+
+/// Defines what happens when a message handler panics.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PanicPolicy {
+    /// The panic unwinds through the code which processes messages.
+    #[default]
+    Propagate,
+    /// The panic is caught and reported as [`MessageHandlerError::Panicked`].
+    Catch,
+}
+
+#[cfg(feature = "std")]
+impl PanicPolicy {
+    // Calls the message handler with the given message according to the policy.
+    pub(crate) fn call(
+        self,
+        f: &mut dyn ErasedMessageHandler,
+        msg: Arc<dyn Message>,
+    ) -> Result<(), MessageHandlerError> {
+        match self {
+            PanicPolicy::Propagate => f.call(msg),
+            PanicPolicy::Catch => {
+                let msg_type_name = msg.type_name();
+
+                panic::catch_unwind(AssertUnwindSafe(|| f.call(msg))).unwrap_or_else(|payload| {
+                    Err(MessageHandlerError::Panicked {
+                        msg_type_name,
+                        panic_message: panic_message(payload.as_ref()),
+                    })
+                })
+            }
+        }
+    }
+}
+
+// Extracts the message from the panic payload if it is a string.
+#[cfg(feature = "std")]
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> Option<String> {
+    payload
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
 }
 
 /// An iterator which yields messages.
@@ -150,11 +269,108 @@ pub trait MessageIterator: Iterator<Item = Arc<dyn Message>> {
         H: ErasedMessageHandler + 'f,
     {
         HandleMessage {
+            #[cfg(feature = "std")]
+            panic_policy: self.panic_policy(),
             iter: self,
             f: Box::new(f.into_message_handler()),
         }
     }
 
+    /// Creates an iterator which yields only messages of the given type.
+    ///
+    /// Messages of other types are received and dropped.
+    fn filter_type<M: Message>(self) -> FilterType<Self, M>
+    where
+        Self: Sized,
+    {
+        FilterType {
+            iter: self,
+            _msg_type: PhantomData,
+        }
+    }
+
+    /// Creates a message iterator which yields only messages which were pending
+    /// when it was created.
+    ///
+    /// Messages which are published while the iterator is running are left
+    /// for the next iteration.
+    fn take_while_pending(self) -> TakeWhilePending<Self>
+    where
+        Self: Sized,
+    {
+        TakeWhilePending {
+            remaining: self.size_hint().0,
+            iter: self,
+        }
+    }
+
+    /// Creates a message iterator which yields at most `n` messages.
+    fn limit(self, n: usize) -> Limit<Self>
+    where
+        Self: Sized,
+    {
+        Limit {
+            iter: self,
+            remaining: n,
+        }
+    }
+
+    /// Creates a message iterator which calls `f` on each message of the given type
+    /// and passes all messages on.
+    fn inspect_type<M, F>(self, f: F) -> InspectType<Self, M, F>
+    where
+        Self: Sized,
+        M: Message,
+        F: FnMut(&M),
+    {
+        InspectType {
+            iter: self,
+            f,
+            _msg_type: PhantomData,
+        }
+    }
+
+    /// Creates a message iterator which yields the messages of this iterator
+    /// and then the messages of `other`.
+    ///
+    /// Unlike [`Iterator::chain`], the returned iterator keeps the panic policy
+    /// of the chained iterators if they have the same one.
+    fn chain_messages<U: MessageIterator>(self, other: U) -> ChainMessages<Self, U>
+    where
+        Self: Sized,
+    {
+        #[cfg(feature = "std")]
+        let panic_policy = if self.panic_policy() == other.panic_policy() {
+            self.panic_policy()
+        } else {
+            PanicPolicy::default()
+        };
+
+        ChainMessages {
+            #[cfg(feature = "std")]
+            panic_policy,
+            iter: self.chain(other),
+        }
+    }
+
+    /// Consumes the iterator, splitting messages into the ones of the given type
+    /// and all the others.
+    fn partition_by_type<M: Message>(self) -> (Vec<Arc<M>>, Vec<Arc<dyn Message>>)
+    where
+        Self: Sized,
+    {
+        let mut typed_msgs = Vec::new();
+        let mut other_msgs = Vec::new();
+        for msg in self {
+            match downcast_message(msg) {
+                Ok(msg) => typed_msgs.push(msg),
+                Err(msg) => other_msgs.push(msg),
+            }
+        }
+
+        (typed_msgs, other_msgs)
+    }
+
     /// Runs an iterator.
     fn run(self)
     where
@@ -162,24 +378,45 @@ pub trait MessageIterator: Iterator<Item = Arc<dyn Message>> {
     {
         self.for_each(|_| {});
     }
+
+    /// Returns the policy which is applied when a message handler of the iterator panics.
+    ///
+    /// Iterators created by a subscription use its [`ErasedSubscription::panic_policy`].
+    /// Iterators chained with [`Iterator::chain`] propagate panics,
+    /// see [`MessageIterator::chain_messages`].
+    #[cfg(feature = "std")]
+    fn panic_policy(&self) -> PanicPolicy {
+        PanicPolicy::default()
+    }
 }
 
 /// An iterator which yields messages from one [`ErasedSubscription`].
 ///
 /// This `struct` is created by [`ErasedSubscription::message_iter`].
+#[cfg(feature = "std")]
 pub struct MessageIter<'s> {
     pub(crate) sub: &'s dyn ErasedSubscription,
 }
 
+#[cfg(feature = "std")]
 impl Iterator for MessageIter<'_> {
     type Item = Arc<dyn Message>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.sub.recv_message()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.sub.pending_messages(), None)
+    }
 }
 
-impl MessageIterator for MessageIter<'_> {}
+#[cfg(feature = "std")]
+impl MessageIterator for MessageIter<'_> {
+    fn panic_policy(&self) -> PanicPolicy {
+        self.sub.panic_policy()
+    }
+}
 
 /// A message iterator which handles messages with `f`.
 ///
@@ -187,6 +424,8 @@ impl MessageIterator for MessageIter<'_> {}
 pub struct HandleMessage<'f, I> {
     iter: I,
     f: Box<dyn ErasedMessageHandler + 'f>,
+    #[cfg(feature = "std")]
+    panic_policy: PanicPolicy,
 }
 
 impl<I: Iterator<Item = Arc<dyn Message>>> Iterator for HandleMessage<'_, I> {
@@ -195,14 +434,203 @@ impl<I: Iterator<Item = Arc<dyn Message>>> Iterator for HandleMessage<'_, I> {
     fn next(&mut self) -> Option<Self::Item> {
         let msg = self.iter.next();
         if let Some(msg) = msg.clone() {
+            #[cfg(feature = "std")]
+            let _ = self.panic_policy.call(&mut *self.f, msg);
+            #[cfg(not(feature = "std"))]
             let _ = self.f.call(msg);
         }
 
         msg
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
 }
 
-impl<I: Iterator<Item = Arc<dyn Message>>> MessageIterator for HandleMessage<'_, I> {}
+impl<I: MessageIterator> MessageIterator for HandleMessage<'_, I> {
+    #[cfg(feature = "std")]
+    fn panic_policy(&self) -> PanicPolicy {
+        self.iter.panic_policy()
+    }
+}
+
+/// An iterator which yields only messages of type `M`.
+///
+/// This `struct` is created by [`MessageIterator::filter_type`].
+pub struct FilterType<I, M> {
+    iter: I,
+    _msg_type: PhantomData<fn() -> M>,
+}
+
+impl<I: Iterator<Item = Arc<dyn Message>>, M: Message> Iterator for FilterType<I, M> {
+    type Item = Arc<M>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find_map(|msg| downcast_message(msg).ok())
+    }
 
-impl<I: MessageIterator + ?Sized> MessageIterator for Box<I> {}
-impl<I: MessageIterator + ?Sized> MessageIterator for &mut I {}
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+/// A message iterator which yields only messages which were pending when it was created.
+///
+/// This `struct` is created by [`MessageIterator::take_while_pending`].
+pub struct TakeWhilePending<I> {
+    iter: I,
+    remaining: usize,
+}
+
+impl<I: Iterator<Item = Arc<dyn Message>>> Iterator for TakeWhilePending<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, _) = self.iter.size_hint();
+
+        (lower.min(self.remaining), Some(self.remaining))
+    }
+}
+
+impl<I: MessageIterator> MessageIterator for TakeWhilePending<I> {
+    #[cfg(feature = "std")]
+    fn panic_policy(&self) -> PanicPolicy {
+        self.iter.panic_policy()
+    }
+}
+
+/// A message iterator which yields at most a limited number of messages.
+///
+/// This `struct` is created by [`MessageIterator::limit`].
+pub struct Limit<I> {
+    iter: I,
+    remaining: usize,
+}
+
+impl<I: Iterator<Item = Arc<dyn Message>>> Iterator for Limit<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        let upper = upper.map_or(self.remaining, |upper| upper.min(self.remaining));
+
+        (lower.min(self.remaining), Some(upper))
+    }
+}
+
+impl<I: MessageIterator> MessageIterator for Limit<I> {
+    #[cfg(feature = "std")]
+    fn panic_policy(&self) -> PanicPolicy {
+        self.iter.panic_policy()
+    }
+}
+
+/// A message iterator which calls `f` on each message of type `M`.
+///
+/// This `struct` is created by [`MessageIterator::inspect_type`].
+pub struct InspectType<I, M, F> {
+    iter: I,
+    f: F,
+    _msg_type: PhantomData<fn(&M)>,
+}
+
+impl<I, M, F> Iterator for InspectType<I, M, F>
+where
+    I: Iterator<Item = Arc<dyn Message>>,
+    M: Message,
+    F: FnMut(&M),
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let msg = self.iter.next()?;
+        if let Some(typed_msg) = (*msg).as_any_ref().downcast_ref::<M>() {
+            (self.f)(typed_msg);
+        }
+
+        Some(msg)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<I, M, F> MessageIterator for InspectType<I, M, F>
+where
+    I: MessageIterator,
+    M: Message,
+    F: FnMut(&M),
+{
+    #[cfg(feature = "std")]
+    fn panic_policy(&self) -> PanicPolicy {
+        self.iter.panic_policy()
+    }
+}
+
+/// A message iterator which yields the messages of two iterators one after another.
+///
+/// This `struct` is created by [`MessageIterator::chain_messages`].
+pub struct ChainMessages<A, B> {
+    iter: Chain<A, B>,
+    #[cfg(feature = "std")]
+    panic_policy: PanicPolicy,
+}
+
+impl<A, B> Iterator for ChainMessages<A, B>
+where
+    A: Iterator<Item = Arc<dyn Message>>,
+    B: Iterator<Item = Arc<dyn Message>>,
+{
+    type Item = Arc<dyn Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<A: MessageIterator, B: MessageIterator> MessageIterator for ChainMessages<A, B> {
+    #[cfg(feature = "std")]
+    fn panic_policy(&self) -> PanicPolicy {
+        self.panic_policy
+    }
+}
+
+impl<A: MessageIterator, B: MessageIterator> MessageIterator for Chain<A, B> {}
+
+impl<I: MessageIterator + ?Sized> MessageIterator for Box<I> {
+    #[cfg(feature = "std")]
+    fn panic_policy(&self) -> PanicPolicy {
+        (**self).panic_policy()
+    }
+}
+
+impl<I: MessageIterator + ?Sized> MessageIterator for &mut I {
+    #[cfg(feature = "std")]
+    fn panic_policy(&self) -> PanicPolicy {
+        (**self).panic_policy()
+    }
+}
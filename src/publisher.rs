@@ -1,5 +1,6 @@
 use crate::*;
 
+use std::marker::PhantomData;
 use std::sync::Arc;
 
 /// A sender of messages.
@@ -13,4 +14,90 @@ pub trait Publisher {
     fn publish(&self, msg: Arc<dyn Message>) {
         let _ = self.message_broker().publish_message(msg);
     }
+
+    /// Wraps the given message into [`Arc`] and sends it to the message broker.
+    fn publish_value<M: Message>(&self, msg: M)
+    where
+        Self: Sized,
+    {
+        self.publish(Arc::new(msg));
+    }
+
+    /// Sends all given messages to the message broker at once.
+    ///
+    /// See [`MessageBroker::publish_message_batch`]
+    fn publish_batch(&self, msgs: impl IntoIterator<Item = Arc<dyn Message>>)
+    where
+        Self: Sized,
+    {
+        let _ = self.message_broker().publish_batch(msgs);
+    }
+}
+
+/// A sender of messages of the given type.
+///
+/// [`TypedPublisher`] keeps the message topic of its type, so it doesn't look it up
+/// in the message broker on every publish. Messages are delivered by the topic directly,
+/// so a message broker which overrides [`MessageBroker::publish_message`]
+/// is bypassed, but its [`MessageBroker::dead_letter`] is still used.
+pub struct TypedPublisher<M: Message> {
+    msg_broker: Arc<dyn MessageBroker>,
+    msg_topic: Arc<MessageTopic>,
+    _msg_type: PhantomData<fn(M)>,
+}
+
+impl<M: Message> TypedPublisher<M> {
+    /// Creates a new [`TypedPublisher`] which publishes messages to the given message broker.
+    pub fn new(msg_broker: Arc<dyn MessageBroker>) -> Self {
+        Self {
+            msg_topic: msg_broker.get_message_topic(MessageTypeId::of::<M>()),
+            msg_broker,
+            _msg_type: PhantomData,
+        }
+    }
+
+    /// Returns the message broker which the publisher is connected to.
+    pub fn message_broker(&self) -> Arc<dyn MessageBroker> {
+        Arc::clone(&self.msg_broker)
+    }
+
+    /// Sends the given message to all subscribers which are listening for messages of its type.
+    ///
+    /// See [`MessageBroker::publish_message`]
+    pub fn publish(&self, msg: impl Into<Arc<M>>) -> Result<(), MessageBrokerError> {
+        let msg: Arc<M> = msg.into();
+
+        broker::deliver_message(&*self.msg_broker, &self.msg_topic, msg)
+    }
+
+    /// Sends all given messages at once, locking the message topic only once.
+    ///
+    /// See [`MessageBroker::publish_message_batch`]
+    pub fn publish_batch<I>(&self, msgs: I) -> Result<(), MessageBrokerError>
+    where
+        I: IntoIterator,
+        I::Item: Into<Arc<M>>,
+    {
+        let msgs = msgs
+            .into_iter()
+            .map(|msg| {
+                let msg: Arc<M> = msg.into();
+                msg as Arc<dyn Message>
+            })
+            .collect();
+
+        broker::deliver_message_batch(&*self.msg_broker, &self.msg_topic, msgs, &mut || {
+            self.msg_broker.next_sequence_number()
+        })
+    }
+}
+
+impl<M: Message> Clone for TypedPublisher<M> {
+    fn clone(&self) -> Self {
+        Self {
+            msg_broker: Arc::clone(&self.msg_broker),
+            msg_topic: Arc::clone(&self.msg_topic),
+            _msg_type: PhantomData,
+        }
+    }
 }
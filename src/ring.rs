@@ -0,0 +1,57 @@
+use alloc::boxed::Box;
+
+// A first-in, first-out queue with a fixed capacity which never allocates
+// after it is created.
+pub(crate) struct RingBuffer<T> {
+    slots: Box<[Option<T>]>,
+    // The index of the slot which holds the oldest value.
+    head: usize,
+    len: usize,
+}
+
+impl<T> RingBuffer<T> {
+    // Creates a new [`RingBuffer`] which can hold `capacity` values.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity).map(|_| None).collect(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    // Returns the maximal number of values which can be held in the buffer.
+    pub(crate) fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Returns the number of values which are held in the buffer.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    // Appends the value to the back of the buffer, returning it back if the buffer is full.
+    pub(crate) fn push_back(&mut self, value: T) -> Result<(), T> {
+        if self.len == self.capacity() {
+            return Err(value);
+        }
+
+        let tail = (self.head + self.len) % self.capacity();
+        self.slots[tail] = Some(value);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    // Removes the oldest value from the buffer and returns it.
+    pub(crate) fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let value = self.slots[self.head].take();
+        self.head = (self.head + 1) % self.capacity();
+        self.len -= 1;
+
+        value
+    }
+}
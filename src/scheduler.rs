@@ -0,0 +1,441 @@
+use crate::*;
+
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::task::{Wake, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A source of the current time which drives a [`Scheduler`] and the message expiry
+/// and dedup windows of a [`DefaultMessageBroker`].
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Registers the waker which must be woken whenever the time changes
+    /// other than by flowing naturally, e.g. when a [`MockClock`] is advanced.
+    fn register_waker(&self, _waker: Waker) {}
+}
+
+/// A [`Clock`] which returns the real time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A [`Clock`] which stands still until it is advanced manually, so scheduled
+/// and expiring messages can be tested without waiting.
+pub struct MockClock {
+    now: Mutex<Instant>,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl MockClock {
+    /// Creates a new [`MockClock`] which starts at the current real time.
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new(Instant::now()),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Moves the time forward by the given duration and wakes the schedulers
+    /// which use the clock.
+    pub fn advance(&self, duration: Duration) {
+        *util::lock(&self.now) += duration;
+        util::lock(&self.wakers).iter().for_each(Waker::wake_by_ref);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *util::lock(&self.now)
+    }
+
+    fn register_waker(&self, waker: Waker) {
+        util::lock(&self.wakers).push(waker);
+    }
+}
+
+// Produces the messages which are published by a scheduled task.
+enum TaskKind {
+    Once(Arc<dyn Message>),
+    Every {
+        period: Duration,
+        factory: Box<dyn FnMut() -> Arc<dyn Message> + Send>,
+    },
+}
+
+// A message which waits in the scheduler until its deadline.
+struct ScheduledTask {
+    // `None` if the deadline overflows [`Instant`], so the task is never due.
+    deadline: Option<Instant>,
+    id: u64,
+    is_cancelled: Arc<AtomicBool>,
+    kind: TaskKind,
+}
+
+impl PartialEq for ScheduledTask {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for ScheduledTask {}
+
+impl PartialOrd for ScheduledTask {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+// The earliest task is the greatest one, so it is on the top of [`BinaryHeap`],
+// while tasks which are never due are the least ones.
+// Tasks with the same deadline are run in the order they were scheduled.
+impl Ord for ScheduledTask {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.deadline.is_none(), other.deadline, other.id).cmp(&(
+            self.deadline.is_none(),
+            self.deadline,
+            self.id,
+        ))
+    }
+}
+
+impl ScheduledTask {
+    fn is_due(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|deadline| deadline <= now)
+    }
+}
+
+struct SchedulerState {
+    tasks: BinaryHeap<ScheduledTask>,
+    next_id: u64,
+    is_shut_down: bool,
+}
+
+// The state which is shared between the scheduler, its timer thread and handles.
+struct SchedulerShared {
+    msg_broker: Arc<dyn MessageBroker>,
+    clock: Arc<dyn Clock>,
+    state: Mutex<SchedulerState>,
+    // Notified when a task is scheduled or the clock is changed.
+    cvar: Condvar,
+    // Held while due tasks are run, so each of them is run only once and in order.
+    running: Mutex<()>,
+}
+
+impl SchedulerShared {
+    fn lock(&self) -> MutexGuard<'_, SchedulerState> {
+        util::lock(&self.state)
+    }
+
+    fn wake(&self) {
+        let _state = self.lock();
+        self.cvar.notify_all();
+    }
+
+    fn schedule(self: &Arc<Self>, deadline: Option<Instant>, kind: TaskKind) -> ScheduleHandle {
+        let is_cancelled = Arc::new(AtomicBool::new(false));
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.tasks.push(ScheduledTask {
+            deadline,
+            id,
+            is_cancelled: Arc::clone(&is_cancelled),
+            kind,
+        });
+        drop(state);
+        self.cvar.notify_all();
+
+        ScheduleHandle {
+            id,
+            is_cancelled,
+            shared: Arc::downgrade(self),
+        }
+    }
+
+    fn cancel(&self, id: u64) {
+        self.lock().tasks.retain(|task| task.id != id);
+    }
+
+    // Publishes the messages of all tasks which are due and returns their number.
+    fn run_pending(&self) -> usize {
+        let _running = util::lock(&self.running);
+        let now = self.clock.now();
+        let mut published = 0;
+        loop {
+            let mut state = self.lock();
+            if state.tasks.peek().is_none_or(|task| !task.is_due(now)) {
+                return published;
+            }
+            let mut task = state.tasks.pop().unwrap();
+            drop(state);
+
+            // The task could be cancelled after it was taken out of the queue.
+            if task.is_cancelled.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            let msg = match task.kind {
+                TaskKind::Once(ref msg) => Some(Arc::clone(msg)),
+                // A panicking factory must not bring the timer thread down,
+                // the message of this period is skipped.
+                TaskKind::Every {
+                    ref mut factory, ..
+                } => panic::catch_unwind(AssertUnwindSafe(factory)).ok(),
+            };
+            if let Some(msg) = msg {
+                let _ = self.msg_broker.publish_message(msg);
+                published += 1;
+            }
+
+            if let TaskKind::Every { period, .. } = task.kind {
+                if task.is_cancelled.load(Ordering::SeqCst) {
+                    continue;
+                }
+
+                // Missed periods are skipped instead of being published in a burst.
+                task.deadline = task
+                    .deadline
+                    .and_then(|deadline| deadline.checked_add(period))
+                    .filter(|&deadline| deadline > now)
+                    .or_else(|| now.checked_add(period));
+                self.lock().tasks.push(task);
+            }
+        }
+    }
+
+    // Runs due tasks until the scheduler is shut down.
+    fn run_timer(&self) {
+        let mut state = self.lock();
+        loop {
+            if state.is_shut_down {
+                return;
+            }
+
+            let now = self.clock.now();
+            // Tasks which are never due are waited for like no tasks at all.
+            let Some(deadline) = state.tasks.peek().and_then(|task| task.deadline) else {
+                state = self.cvar.wait(state).unwrap_or_else(|err| err.into_inner());
+                continue;
+            };
+
+            if deadline <= now {
+                drop(state);
+                self.run_pending();
+                state = self.lock();
+                continue;
+            }
+
+            state = self
+                .cvar
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+    }
+}
+
+// The waker which makes the timer thread check the clock again.
+struct SchedulerWaker {
+    shared: Weak<SchedulerShared>,
+}
+
+impl Wake for SchedulerWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(shared) = self.shared.upgrade() {
+            shared.wake();
+        }
+    }
+}
+
+/// A handle which can cancel a message scheduled by a [`Scheduler`].
+///
+/// Dropping the handle doesn't cancel the message.
+pub struct ScheduleHandle {
+    id: u64,
+    is_cancelled: Arc<AtomicBool>,
+    shared: Weak<SchedulerShared>,
+}
+
+impl ScheduleHandle {
+    /// Cancels the scheduled message, so it isn't published anymore.
+    ///
+    /// A message which is being published right now may still be published.
+    pub fn cancel(&self) {
+        self.is_cancelled.store(true, Ordering::SeqCst);
+        if let Some(shared) = self.shared.upgrade() {
+            shared.cancel(self.id);
+        }
+    }
+
+    /// Returns if the scheduled message was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.is_cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// A timer which publishes messages in the given message broker at scheduled times.
+///
+/// Messages are published by a single timer thread in the order of their deadlines.
+///
+/// Dropping the scheduler stops the timer thread, messages which weren't published
+/// are dropped.
+pub struct Scheduler {
+    shared: Arc<SchedulerShared>,
+    timer: Option<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    /// Creates a new [`Scheduler`] which publishes messages in the given message broker
+    /// according to the real time.
+    pub fn new(msg_broker: Arc<dyn MessageBroker>) -> Self {
+        Self::with_clock(msg_broker, Arc::new(SystemClock))
+    }
+
+    /// Creates a new [`Scheduler`] which publishes messages in the given message broker
+    /// according to the given clock.
+    pub fn with_clock(msg_broker: Arc<dyn MessageBroker>, clock: Arc<dyn Clock>) -> Self {
+        let shared = Arc::new(SchedulerShared {
+            msg_broker,
+            clock,
+            state: Mutex::new(SchedulerState {
+                tasks: BinaryHeap::new(),
+                next_id: 0,
+                is_shut_down: false,
+            }),
+            cvar: Condvar::new(),
+            running: Mutex::new(()),
+        });
+        shared
+            .clock
+            .register_waker(Waker::from(Arc::new(SchedulerWaker {
+                shared: Arc::downgrade(&shared),
+            })));
+
+        let timer = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("lps-scheduler".to_string())
+                .spawn(move || shared.run_timer())
+                .expect("failed to spawn a scheduler thread")
+        };
+
+        Self {
+            shared,
+            timer: Some(timer),
+        }
+    }
+
+    /// Returns a message broker which receives scheduled messages.
+    pub fn message_broker(&self) -> Arc<dyn MessageBroker> {
+        Arc::clone(&self.shared.msg_broker)
+    }
+
+    /// Returns the clock which drives the scheduler.
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.shared.clock)
+    }
+
+    /// Publishes the given message after the given delay.
+    pub fn publish_after<M: Message>(
+        &self,
+        delay: Duration,
+        msg: impl Into<Arc<M>>,
+    ) -> ScheduleHandle {
+        let msg: Arc<M> = msg.into();
+
+        // A delay which overflows [`Instant`] is never over.
+        let deadline = self.shared.clock.now().checked_add(delay);
+        self.shared.schedule(deadline, TaskKind::Once(msg))
+    }
+
+    /// Publishes the given message at the given time, or right away if it has already passed.
+    pub fn publish_at<M: Message>(
+        &self,
+        deadline: Instant,
+        msg: impl Into<Arc<M>>,
+    ) -> ScheduleHandle {
+        let msg: Arc<M> = msg.into();
+
+        self.shared.schedule(Some(deadline), TaskKind::Once(msg))
+    }
+
+    /// Publishes a message created by the given factory every `period`,
+    /// starting one period from now.
+    ///
+    /// If the timer falls behind, missed periods are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero, since the message would be due again right away
+    /// and [`Scheduler::run_pending`] would never return.
+    pub fn publish_every<M, F>(&self, period: Duration, mut factory: F) -> ScheduleHandle
+    where
+        M: Message,
+        F: FnMut() -> M + Send + 'static,
+    {
+        assert!(
+            !period.is_zero(),
+            "the period of a repeating message must be greater than zero"
+        );
+
+        // A period which overflows [`Instant`] never passes.
+        self.shared.schedule(
+            self.shared.clock.now().checked_add(period),
+            TaskKind::Every {
+                period,
+                factory: Box::new(move || Arc::new(factory())),
+            },
+        )
+    }
+
+    /// Publishes the messages which are due on the current thread without waiting
+    /// for the timer thread and returns their number.
+    ///
+    /// When this method returns, every message which was due is published.
+    pub fn run_pending(&self) -> usize {
+        self.shared.run_pending()
+    }
+
+    /// Returns the number of scheduled messages which weren't published yet.
+    ///
+    /// A repeating message is counted once.
+    pub fn len(&self) -> usize {
+        self.shared.lock().tasks.len()
+    }
+
+    /// Returns `true` if no messages are scheduled.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shared.lock().is_shut_down = true;
+        self.shared.cvar.notify_all();
+        if let Some(timer) = self.timer.take() {
+            let _ = timer.join();
+        }
+    }
+}
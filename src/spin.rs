@@ -0,0 +1,77 @@
+use core::cell::UnsafeCell;
+use core::hint;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// A mutual exclusion primitive which busy-waits until the lock is released,
+// so it doesn't need the standard library.
+//
+// The lock mustn't be taken by an interrupt handler which can preempt the holder
+// of the lock on the same core, otherwise the handler spins forever.
+pub(crate) struct SpinMutex<T: ?Sized> {
+    is_locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: the data is accessed only through the guard, and only one guard exists at a time.
+unsafe impl<T: ?Sized + Send> Send for SpinMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    pub(crate) const fn new(data: T) -> Self {
+        Self {
+            is_locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SpinMutex<T> {
+    // Acquires the lock, spinning until it is released by its current holder.
+    pub(crate) fn lock(&self) -> SpinMutexGuard<'_, T> {
+        while self
+            .is_locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.is_locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+
+        SpinMutexGuard { mutex: self }
+    }
+}
+
+impl<T: Default> Default for SpinMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+// Releases the lock when it is dropped, even if the holder panics.
+pub(crate) struct SpinMutexGuard<'m, T: ?Sized> {
+    mutex: &'m SpinMutex<T>,
+}
+
+impl<T: ?Sized> Deref for SpinMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the guard holds the lock, so nobody else accesses the data.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SpinMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the guard holds the lock, so nobody else accesses the data.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SpinMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.is_locked.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::{ring::*, spin::*, *};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// A bounded queue of messages which are waiting to be received by a subscription.
+struct SpinMessageChannel {
+    msgs: SpinMutex<RingBuffer<Arc<dyn Message>>>,
+    is_active: AtomicBool,
+}
+
+impl SpinMessageChannel {
+    fn recv(&self) -> Option<Arc<dyn Message>> {
+        self.msgs.lock().pop_front()
+    }
+
+    fn pending(&self) -> usize {
+        self.msgs.lock().len()
+    }
+}
+
+/// A message broker which doesn't need the standard library.
+///
+/// Topics are guarded by spin locks and every subscription keeps its messages
+/// in a ring buffer of a fixed capacity, so publishing never allocates.
+/// The broker can be created in a `static`, since [`SpinMessageBroker::new`] is `const`.
+///
+/// Messages mustn't be published from an interrupt handler which can preempt
+/// a thread using the same broker on the same core.
+#[derive(Default)]
+pub struct SpinMessageBroker {
+    msg_topics_map: SpinMutex<BTreeMap<MessageTypeId, Vec<Arc<SpinMessageChannel>>>>,
+}
+
+impl SpinMessageBroker {
+    /// Creates a new [`SpinMessageBroker`] without any subscriptions.
+    ///
+    /// It doesn't allocate, so it can be used to initialize a `static`.
+    pub const fn new() -> Self {
+        Self {
+            msg_topics_map: SpinMutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Sends the given message to all active subscriptions to messages of its type.
+    ///
+    /// If the buffer of some subscription is full, the message is still sent
+    /// to the other ones and [`SpinMessageBrokerError::BufferFull`] is returned.
+    pub fn publish_message(&self, msg: Arc<dyn Message>) -> Result<(), SpinMessageBrokerError> {
+        let msg_topics_map = self.msg_topics_map.lock();
+        let Some(channels) = msg_topics_map.get(&msg.type_id()) else {
+            return Err(SpinMessageBrokerError::NoSubscribers {
+                msg_type_name: msg.type_name(),
+            });
+        };
+
+        channels
+            .iter()
+            .filter(|channel| channel.is_active.load(Ordering::SeqCst))
+            .map(|channel| {
+                channel
+                    .msgs
+                    .lock()
+                    .push_back(Arc::clone(&msg))
+                    .map_err(|msg| SpinMessageBrokerError::BufferFull {
+                        msg_type_name: msg.type_name(),
+                    })
+            })
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Wraps the given message into [`Arc`] and publishes it.
+    pub fn publish_value<M: Message>(&self, msg: M) -> Result<(), SpinMessageBrokerError> {
+        self.publish_message(Arc::new(msg))
+    }
+
+    fn create_channel(
+        &self,
+        msg_type_id: MessageTypeId,
+        capacity: usize,
+    ) -> Arc<SpinMessageChannel> {
+        let channel = Arc::new(SpinMessageChannel {
+            msgs: SpinMutex::new(RingBuffer::with_capacity(capacity)),
+            is_active: AtomicBool::new(true),
+        });
+        self.msg_topics_map
+            .lock()
+            .entry(msg_type_id)
+            .or_default()
+            .push(Arc::clone(&channel));
+
+        channel
+    }
+
+    fn destroy_channel(&self, msg_type_id: MessageTypeId, channel: &Arc<SpinMessageChannel>) {
+        let mut msg_topics_map = self.msg_topics_map.lock();
+        let Some(channels) = msg_topics_map.get_mut(&msg_type_id) else {
+            return;
+        };
+
+        channels.retain(|other| !Arc::ptr_eq(other, channel));
+        if channels.is_empty() {
+            msg_topics_map.remove(&msg_type_id);
+        }
+    }
+}
+
+/// A type which is used for receiving messages of a specific type
+/// from the [`SpinMessageBroker`].
+///
+/// The subscription is registered while it exists and is unregistered when it is dropped.
+pub struct SpinSubscription<'b, M: Message> {
+    msg_broker: &'b SpinMessageBroker,
+    channel: Arc<SpinMessageChannel>,
+    _msg_type: PhantomData<fn() -> M>,
+}
+
+impl<'b, M: Message> SpinSubscription<'b, M> {
+    /// Creates a new [`SpinSubscription`] which is registered in the given message broker
+    /// and can keep up to `capacity` messages, at least one.
+    pub fn new(msg_broker: &'b SpinMessageBroker, capacity: usize) -> Self {
+        Self {
+            msg_broker,
+            channel: msg_broker.create_channel(MessageTypeId::of::<M>(), capacity.max(1)),
+            _msg_type: PhantomData,
+        }
+    }
+
+    /// Returns a message broker which sends messages to this subscription.
+    pub fn message_broker(&self) -> &'b SpinMessageBroker {
+        self.msg_broker
+    }
+
+    /// Returns the maximal number of messages which can wait in the subscription.
+    pub fn capacity(&self) -> usize {
+        self.channel.msgs.lock().capacity()
+    }
+
+    /// Returns if the subscription is active.
+    pub fn is_active(&self) -> bool {
+        self.channel.is_active.load(Ordering::SeqCst)
+    }
+
+    /// Activates the subscription if it was deactivated before.
+    pub fn activate(&self) {
+        self.channel.is_active.store(true, Ordering::SeqCst);
+    }
+
+    /// Dectivates the subscription, in other words temporary makes it stop receiving messages.
+    pub fn deactivate(&self) {
+        self.channel.is_active.store(false, Ordering::SeqCst);
+    }
+
+    /// Receives one message if there is any.
+    pub fn recv_message(&self) -> Option<Arc<M>> {
+        self.channel
+            .recv()
+            .map(|msg| downcast_message(msg).ok().unwrap())
+    }
+
+    /// Returns the number of messages which are waiting to be received.
+    pub fn pending_messages(&self) -> usize {
+        self.channel.pending()
+    }
+
+    /// Returns an iterator that will attempt to yield all pending messages.
+    pub fn message_iter(&self) -> SpinMessageIter<'_> {
+        SpinMessageIter {
+            channel: &self.channel,
+        }
+    }
+}
+
+impl<M: Message> Drop for SpinSubscription<'_, M> {
+    fn drop(&mut self) {
+        self.msg_broker
+            .destroy_channel(MessageTypeId::of::<M>(), &self.channel);
+    }
+}
+
+/// An iterator which yields messages from one [`SpinSubscription`].
+///
+/// This `struct` is created by [`SpinSubscription::message_iter`].
+pub struct SpinMessageIter<'s> {
+    channel: &'s SpinMessageChannel,
+}
+
+impl Iterator for SpinMessageIter<'_> {
+    type Item = Arc<dyn Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.channel.recv()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.channel.pending(), None)
+    }
+}
+
+impl MessageIterator for SpinMessageIter<'_> {}
+
+/// The reason why [`SpinMessageBroker::publish_message`] didn't deliver a message
+/// to every subscription.
+#[derive(Debug)]
+pub enum SpinMessageBrokerError {
+    /// Nobody is subscribed to messages of the given type.
+    NoSubscribers { msg_type_name: &'static str },
+    /// The buffer of some subscription is full, so it didn't receive
+    /// the message of the given type.
+    BufferFull { msg_type_name: &'static str },
+}
+
+impl fmt::Display for SpinMessageBrokerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpinMessageBrokerError::NoSubscribers { msg_type_name } => write!(
+                f,
+                "nobody is subscribed to messages of type `{}`",
+                msg_type_name
+            ),
+            SpinMessageBrokerError::BufferFull { msg_type_name } => write!(
+                f,
+                "the buffer of a subscription is full and rejected a message of type `{}`",
+                msg_type_name
+            ),
+        }
+    }
+}
+
+impl Error for SpinMessageBrokerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+
+    struct TestMsg0(u32);
+
+    impl Message for TestMsg0 {}
+
+    struct TestMsg1(u32);
+
+    impl Message for TestMsg1 {}
+
+    #[test]
+    fn test_spin_broker_without_std() {
+        let broker = SpinMessageBroker::new();
+        assert!(matches!(
+            broker.publish_value(TestMsg0(0)),
+            Err(SpinMessageBrokerError::NoSubscribers { .. })
+        ));
+
+        let sub0: SpinSubscription<TestMsg0> = SpinSubscription::new(&broker, 1);
+        let sub1: SpinSubscription<TestMsg1> = SpinSubscription::new(&broker, 2);
+
+        broker.publish_value(TestMsg0(1)).unwrap();
+        assert!(matches!(
+            broker.publish_value(TestMsg0(2)),
+            Err(SpinMessageBrokerError::BufferFull { .. })
+        ));
+        assert_eq!(1, sub0.recv_message().unwrap().0);
+        assert!(sub0.recv_message().is_none());
+
+        sub1.deactivate();
+        broker.publish_value(TestMsg1(3)).unwrap();
+        sub1.activate();
+        broker.publish_value(TestMsg1(4)).unwrap();
+        broker.publish_value(TestMsg1(5)).unwrap();
+
+        let mut ids = vec![];
+        sub1.message_iter()
+            .handle(|msg: Arc<TestMsg1>| ids.push(msg.0))
+            .run();
+        assert_eq!(vec![4, 5], ids);
+
+        drop(sub0);
+        assert!(broker.publish_value(TestMsg0(6)).is_err());
+    }
+}
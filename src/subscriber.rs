@@ -1,6 +1,8 @@
 use crate::*;
 
 use std::sync::Arc;
+use std::task::Waker;
+use std::time::{Duration, Instant};
 
 /// A receiver of messages.
 ///
@@ -21,8 +23,43 @@ pub trait Subscriber {
     /// by deactivating all its [`Subscription`]s (see [`ErasedSubscription::deactivate`]).
     fn deactivate(&self);
 
+    /// Sets the waker which is woken whenever any [`Subscription`] of the subscriber
+    /// receives a message (see [`ErasedSubscription::set_waker`]).
+    ///
+    /// The default implementation does nothing, so a [`Dispatcher`] can only
+    /// poll the subscriber periodically.
+    fn set_waker(&self, waker: Option<Waker>) {
+        let _ = waker;
+    }
+
     /// Proccesses all messages received from all [`Subscription`]s of the subscriber.
     ///
     /// See [`Subscription::process_messages`]
     fn process_messages(&mut self);
+    /// Proccesses at most `max` messages received from the [`Subscription`]s of the subscriber.
+    ///
+    /// See [`Subscription::process_messages_max`]
+    fn process_messages_max(&mut self, max: usize) -> ProcessedMessages;
+    /// Proccesses messages received from the [`Subscription`]s of the subscriber
+    /// until the given time budget runs out.
+    ///
+    /// The default implementation processes messages one by one
+    /// with [`Subscriber::process_messages_max`], checking the budget before each of them.
+    ///
+    /// See [`Subscription::process_messages_for`]
+    fn process_messages_for(&mut self, budget: Duration) -> ProcessedMessages {
+        let start = Instant::now();
+        let mut processed = self.process_messages_max(0);
+        while processed.has_more && start.elapsed() < budget {
+            let next = self.process_messages_max(1);
+            if next.handled == 0 {
+                break;
+            }
+
+            processed.handled += next.handled;
+            processed.has_more = next.has_more;
+        }
+
+        processed
+    }
 }
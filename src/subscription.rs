@@ -1,8 +1,13 @@
 use crate::*;
 
+use std::error::Error;
+use std::fmt;
 use std::marker::PhantomData;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::ops::{Add, Deref};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::Waker;
+use std::time::{Duration, Instant};
 
 /// A [`Subscription`] with and erased message type.
 pub trait ErasedSubscription: sealed::Sealed {
@@ -14,28 +19,186 @@ pub trait ErasedSubscription: sealed::Sealed {
     /// Returns if the subscription is active.
     fn is_active(&self) -> bool;
 
+    /// Returns if the subscription holds a weak reference to its message broker
+    /// which was dropped.
+    fn is_broker_gone(&self) -> bool;
+
     /// Registers the subscription in the given message broker.
     fn register(&mut self, msg_broker: Arc<dyn MessageBroker>) -> Result<(), SubscriptionError>;
+    /// Registers the subscription in the given message broker, but keeps only
+    /// a weak reference to it, so the subscription doesn't keep the broker alive.
+    ///
+    /// After the broker is dropped, the subscription doesn't receive new messages
+    /// and [`ErasedSubscription::unregister`] returns [`SubscriptionError::BrokerGone`].
+    fn register_weak(
+        &mut self,
+        msg_broker: &Arc<dyn MessageBroker>,
+    ) -> Result<(), SubscriptionError>;
     /// Unregisters the subscription in the given message broker.
     fn unregister(&mut self) -> Result<(), SubscriptionError>;
 
     /// Activates the subscription if it was deactivated before.
     fn activate(&self) -> Result<(), SubscriptionError>;
     /// Dectivates the subscription, in other words temporary makes it stop receiving messages.
+    ///
+    /// Messages which are published while the subscription is deactivated are dropped.
     fn deactivate(&self) -> Result<(), SubscriptionError>;
 
+    /// Returns if the subscription is paused.
+    fn is_paused(&self) -> bool;
+    /// Pauses the subscription, in other words temporary makes it keep messages
+    /// without delivering them.
+    ///
+    /// Unlike [`ErasedSubscription::deactivate`], messages which are published while
+    /// the subscription is paused are delivered after it is resumed. If `capacity` is given,
+    /// at most `capacity` messages published after the subscription was paused are kept,
+    /// the other ones are sent to the dead-letter queue.
+    ///
+    /// A [`MultiSubscription`] keeps up to `capacity` messages of each of its types.
+    fn pause(&self, capacity: Option<usize>) -> Result<(), SubscriptionError>;
+    /// Resumes the subscription if it was paused before.
+    fn resume(&self) -> Result<(), SubscriptionError>;
+
+    /// Sets the waker which is woken whenever a message which can be received
+    /// is sent to the subscription.
+    ///
+    /// The waker is kept when the subscription is registered again.
+    fn set_waker(&self, waker: Option<Waker>);
+
+    /// Returns what happens when a message handler panics while processing messages.
+    fn panic_policy(&self) -> PanicPolicy;
+    /// Sets what happens when a message handler panics while processing messages.
+    fn set_panic_policy(&mut self, panic_policy: PanicPolicy);
+
+    /// Returns if the message broker was shut down, so the subscription
+    /// won't receive new messages.
+    fn is_closed(&self) -> bool;
+
     /// Receives one message if there is any.
+    ///
+    /// Returns `None` both if no message is pending and if the subscription is closed,
+    /// see [`ErasedSubscription::try_recv_message`] to tell these cases apart.
     fn recv_message(&self) -> Option<Arc<dyn Message>>;
+    /// Receives one message if there is any, otherwise returns why there isn't.
+    ///
+    /// [`TryRecvError::Closed`] is returned once the message broker was shut down
+    /// and all messages which were left in the subscription are received.
+    fn try_recv_message(&self) -> Result<Arc<dyn Message>, TryRecvError> {
+        if !self.is_registered() {
+            return Err(TryRecvError::NotRegistered);
+        }
+
+        // Messages aren't sent after the subscription is closed or the broker is dropped,
+        // so it is enough to check it before receiving.
+        let err = if self.is_broker_gone() {
+            TryRecvError::BrokerGone
+        } else if self.is_closed() {
+            TryRecvError::Closed
+        } else {
+            TryRecvError::Empty
+        };
+        self.recv_message().ok_or(err)
+    }
+    /// Returns the number of messages which are waiting to be received.
+    ///
+    /// Expired messages are counted until the subscription skips them.
+    fn pending_messages(&self) -> usize;
+    /// Returns the number of messages which the subscription skipped because
+    /// their time to live ran out (see [`Message::ttl`]).
+    fn expired_messages(&self) -> usize;
+    /// Returns the sequence number of the message which will be received next
+    /// (see [`MessageBroker::next_sequence_number`]).
+    fn peek_sequence_number(&self) -> Option<u64>;
+    /// Receives the message which will be received next only if its sequence number
+    /// is `seq`, so the message can't be taken by another receiver after it was peeked
+    /// (see [`ErasedSubscription::peek_sequence_number`]).
+    fn recv_sequenced_message(&self, seq: u64) -> Option<Arc<dyn Message>>;
     /// Returns an iterator that will attempt to yield all pending messages.
     fn message_iter(&self) -> MessageIter<'_>;
     /// Processes all pending messages by calling the given function on each one.
+    ///
+    /// Messages which the function fails to handle are sent to the dead-letter queue
+    /// (see [`MessageBroker::dead_letter`]). If the function panics, the panic is handled
+    /// according to [`ErasedSubscription::panic_policy`].
     fn process_messages<'f>(&self, f: Box<dyn ErasedMessageHandler + 'f>);
+    /// Processes at most `max` pending messages by calling the given function on each one.
+    fn process_messages_max<'f>(
+        &self,
+        max: usize,
+        f: Box<dyn ErasedMessageHandler + 'f>,
+    ) -> ProcessedMessages;
+    /// Processes pending messages by calling the given function on each one
+    /// until the given time budget runs out.
+    fn process_messages_for<'f>(
+        &self,
+        budget: Duration,
+        f: Box<dyn ErasedMessageHandler + 'f>,
+    ) -> ProcessedMessages;
+}
+
+/// The outcome of processing a limited number of messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ProcessedMessages {
+    /// The number of handled messages.
+    pub handled: usize,
+    /// Whether there are pending messages which weren't handled.
+    pub has_more: bool,
+}
+
+impl Add for ProcessedMessages {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            handled: self.handled + rhs.handled,
+            has_more: self.has_more || rhs.has_more,
+        }
+    }
+}
+
+// A reference to the message broker which a subscription is registered in.
+#[derive(Clone)]
+enum BrokerRef {
+    Strong(Arc<dyn MessageBroker>),
+    // Doesn't keep the message broker alive.
+    Weak(Weak<dyn MessageBroker>),
+}
+
+impl BrokerRef {
+    fn new(msg_broker: &Arc<dyn MessageBroker>, is_weak: bool) -> Self {
+        if is_weak {
+            BrokerRef::Weak(Arc::downgrade(msg_broker))
+        } else {
+            BrokerRef::Strong(Arc::clone(msg_broker))
+        }
+    }
+
+    fn upgrade(&self) -> Option<Arc<dyn MessageBroker>> {
+        match self {
+            BrokerRef::Strong(msg_broker) => Some(Arc::clone(msg_broker)),
+            BrokerRef::Weak(msg_broker) => msg_broker.upgrade(),
+        }
+    }
+
+    fn is_weak(&self) -> bool {
+        matches!(self, BrokerRef::Weak(_))
+    }
+
+    fn is_gone(&self) -> bool {
+        match self {
+            BrokerRef::Strong(_) => false,
+            BrokerRef::Weak(msg_broker) => msg_broker.strong_count() == 0,
+        }
+    }
 }
 
 /// A type which is used for receiving messages of a specific type from the message broker.
 pub struct Subscription<M: Message> {
-    msg_broker: Option<Arc<dyn MessageBroker>>,
+    msg_broker: Option<BrokerRef>,
     msg_recv: Option<channel::MessageReceiver>,
+    queue_group: Option<QueueGroup>,
+    waker: Mutex<Option<Waker>>,
+    panic_policy: PanicPolicy,
     _msg_type: PhantomData<M>,
 }
 
@@ -46,6 +209,9 @@ impl<M: Message> Subscription<M> {
         Self {
             msg_broker: None,
             msg_recv: None,
+            queue_group: None,
+            waker: Mutex::new(None),
+            panic_policy: PanicPolicy::default(),
             _msg_type: PhantomData,
         }
     }
@@ -58,26 +224,130 @@ impl<M: Message> Subscription<M> {
         sub
     }
 
+    /// Creates a new [`Subscription`] which is registered in the ambient message broker.
+    ///
+    /// See [`current_broker`]
+    pub fn ambient() -> Self {
+        Self::new(current_broker())
+    }
+
+    /// Creates a new [`Subscription`] which is registered in the given message broker,
+    /// but doesn't keep it alive.
+    ///
+    /// See [`ErasedSubscription::register_weak`]
+    pub fn new_weak(msg_broker: &Arc<dyn MessageBroker>) -> Self {
+        let mut sub = Self::unregistered();
+        let _ = sub.register_weak(msg_broker);
+
+        sub
+    }
+
+    /// Creates a new [`Subscription`] which is registered in the given message broker
+    /// as a member of the given queue group.
+    pub fn in_queue_group(msg_broker: Arc<dyn MessageBroker>, queue_group: QueueGroup) -> Self {
+        let mut sub = Self::unregistered();
+        let _ = sub.set_queue_group(Some(queue_group));
+        let _ = sub.register(msg_broker);
+
+        sub
+    }
+
+    /// Returns the queue group which the subscription joins when it is registered.
+    pub fn queue_group(&self) -> Option<&QueueGroup> {
+        self.queue_group.as_ref()
+    }
+
+    /// Sets the queue group which the subscription joins when it is registered.
+    ///
+    /// The queue group can't be changed while the subscription is registered.
+    pub fn set_queue_group(
+        &mut self,
+        queue_group: Option<QueueGroup>,
+    ) -> Result<(), SubscriptionError> {
+        if self.is_registered() {
+            return Err(SubscriptionError::AlreadyRegistered);
+        }
+        self.queue_group = queue_group;
+
+        Ok(())
+    }
+
     /// Receives one message if there is any.
+    ///
+    /// Returns `None` both if no message is pending and if the subscription is closed,
+    /// see [`Subscription::try_recv_message`] to tell these cases apart.
     pub fn recv_message(&self) -> Option<Arc<M>> {
         ErasedSubscription::recv_message(self).map(|msg| msg.as_any_arc().downcast().unwrap())
     }
 
+    /// Receives one message if there is any, otherwise returns why there isn't.
+    ///
+    /// See [`ErasedSubscription::try_recv_message`]
+    pub fn try_recv_message(&self) -> Result<Arc<M>, TryRecvError> {
+        ErasedSubscription::try_recv_message(self).map(|msg| msg.as_any_arc().downcast().unwrap())
+    }
+
     /// Processes all pending messages by calling the given function on each one.
     pub fn process_messages<F: FnMut(Arc<M>)>(&self, f: F) {
         ErasedSubscription::process_messages(self, Box::new(f.into_message_handler()));
     }
+
+    /// Processes at most `max` pending messages by calling the given function on each one.
+    pub fn process_messages_max<F: FnMut(Arc<M>)>(&self, max: usize, f: F) -> ProcessedMessages {
+        ErasedSubscription::process_messages_max(self, max, Box::new(f.into_message_handler()))
+    }
+
+    /// Processes pending messages by calling the given function on each one
+    /// until the given time budget runs out.
+    pub fn process_messages_for<F: FnMut(Arc<M>)>(
+        &self,
+        budget: Duration,
+        f: F,
+    ) -> ProcessedMessages {
+        ErasedSubscription::process_messages_for(self, budget, Box::new(f.into_message_handler()))
+    }
+
+    fn register_with(
+        &mut self,
+        msg_broker: &Arc<dyn MessageBroker>,
+        is_weak: bool,
+    ) -> Result<(), SubscriptionError> {
+        if self.is_registered() {
+            return Err(SubscriptionError::AlreadyRegistered);
+        }
+
+        let msg_recv = msg_broker.create_message_channel::<M>(self.queue_group.as_ref());
+        msg_recv.set_waker(util::lock(&self.waker).clone());
+        self.msg_recv = Some(msg_recv);
+        self.msg_broker = Some(BrokerRef::new(msg_broker, is_weak));
+
+        Ok(())
+    }
+
+    /// Converts the subscription into a [`SharedSubscription`] which can be cloned
+    /// to distribute its messages between multiple consumers.
+    pub fn into_shared(self) -> SharedSubscription<M> {
+        SharedSubscription {
+            sub: Arc::new(self),
+        }
+    }
 }
 
 impl<M: Message> ErasedSubscription for Subscription<M> {
     fn message_broker(&self) -> Option<Arc<dyn MessageBroker>> {
-        self.msg_broker.clone()
+        self.msg_broker.as_ref()?.upgrade()
     }
 
     fn is_registered(&self) -> bool {
         self.msg_broker.is_some() && self.msg_recv.is_some()
     }
 
+    fn is_broker_gone(&self) -> bool {
+        self.msg_broker
+            .as_ref()
+            .is_some_and(|msg_broker| msg_broker.is_gone())
+    }
+
     fn is_active(&self) -> bool {
         self.msg_recv
             .as_ref()
@@ -85,14 +355,14 @@ impl<M: Message> ErasedSubscription for Subscription<M> {
     }
 
     fn register(&mut self, msg_broker: Arc<dyn MessageBroker>) -> Result<(), SubscriptionError> {
-        if self.is_registered() {
-            return Err(SubscriptionError::AlreadyRegistered);
-        }
-
-        self.msg_recv = Some(msg_broker.create_message_channel::<M>());
-        self.msg_broker = Some(msg_broker);
+        self.register_with(&msg_broker, false)
+    }
 
-        Ok(())
+    fn register_weak(
+        &mut self,
+        msg_broker: &Arc<dyn MessageBroker>,
+    ) -> Result<(), SubscriptionError> {
+        self.register_with(msg_broker, true)
     }
 
     fn unregister(&mut self) -> Result<(), SubscriptionError> {
@@ -103,6 +373,7 @@ impl<M: Message> ErasedSubscription for Subscription<M> {
             return Err(SubscriptionError::NotRegistered);
         };
 
+        let msg_broker = msg_broker.upgrade().ok_or(SubscriptionError::BrokerGone)?;
         msg_broker.destroy_message_channel(msg_recv);
 
         Ok(())
@@ -126,20 +397,100 @@ impl<M: Message> ErasedSubscription for Subscription<M> {
         Ok(())
     }
 
+    fn is_paused(&self) -> bool {
+        self.msg_recv
+            .as_ref()
+            .is_some_and(|msg_recv| msg_recv.is_paused())
+    }
+
+    fn pause(&self, capacity: Option<usize>) -> Result<(), SubscriptionError> {
+        let Some(ref msg_recv) = self.msg_recv else {
+            return Err(SubscriptionError::NotRegistered);
+        };
+        msg_recv.pause(capacity);
+
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), SubscriptionError> {
+        let Some(ref msg_recv) = self.msg_recv else {
+            return Err(SubscriptionError::NotRegistered);
+        };
+        msg_recv.resume();
+
+        Ok(())
+    }
+
+    fn set_waker(&self, waker: Option<Waker>) {
+        if let Some(ref msg_recv) = self.msg_recv {
+            msg_recv.set_waker(waker.clone());
+        }
+        *util::lock(&self.waker) = waker;
+    }
+
+    fn panic_policy(&self) -> PanicPolicy {
+        self.panic_policy
+    }
+
+    fn set_panic_policy(&mut self, panic_policy: PanicPolicy) {
+        self.panic_policy = panic_policy;
+    }
+
+    fn is_closed(&self) -> bool {
+        self.msg_recv
+            .as_ref()
+            .is_some_and(|msg_recv| msg_recv.is_closed())
+    }
+
     fn recv_message(&self) -> Option<Arc<dyn Message>> {
-        let Some(ref msg_recv) = self.msg_recv else { return None };
+        let msg_recv = self.msg_recv.as_ref()?;
 
         msg_recv.recv()
     }
 
+    fn pending_messages(&self) -> usize {
+        self.msg_recv
+            .as_ref()
+            .map_or(0, |msg_recv| msg_recv.pending())
+    }
+
+    fn expired_messages(&self) -> usize {
+        self.msg_recv
+            .as_ref()
+            .map_or(0, |msg_recv| msg_recv.expired())
+    }
+
+    fn peek_sequence_number(&self) -> Option<u64> {
+        self.msg_recv.as_ref()?.peek_sequence_number()
+    }
+
+    fn recv_sequenced_message(&self, seq: u64) -> Option<Arc<dyn Message>> {
+        self.msg_recv.as_ref()?.recv_sequenced(seq)
+    }
+
     fn message_iter(&self) -> MessageIter<'_> {
         MessageIter { sub: self }
     }
 
     fn process_messages<'f>(&self, mut f: Box<dyn ErasedMessageHandler + 'f>) {
-        while let Some(msg) = self.recv_message() {
-            let _ = f.call(msg);
-        }
+        process_messages_while(self, &mut f, |_| true);
+    }
+
+    fn process_messages_max<'f>(
+        &self,
+        max: usize,
+        mut f: Box<dyn ErasedMessageHandler + 'f>,
+    ) -> ProcessedMessages {
+        process_messages_while(self, &mut f, |handled| handled < max)
+    }
+
+    fn process_messages_for<'f>(
+        &self,
+        budget: Duration,
+        mut f: Box<dyn ErasedMessageHandler + 'f>,
+    ) -> ProcessedMessages {
+        let start = Instant::now();
+        process_messages_while(self, &mut f, |_| start.elapsed() < budget)
     }
 }
 
@@ -155,74 +506,496 @@ impl<M: Message> Drop for Subscription<M> {
     }
 }
 
+/// A [`Subscription`] whose handles can be cloned and sent to other threads.
+///
+/// All handles receive messages from the same message channel, so each message
+/// is received by only one of them. It allows to distribute messages between a pool
+/// of workers, while separate [`Subscription`]s of the same type each receive
+/// every published message.
+///
+/// The subscription is unregistered when the last handle is dropped.
+///
+/// Handles give only shared access to the subscription, so settings which need
+/// `&mut` access, like [`ErasedSubscription::set_panic_policy`], must be applied
+/// before [`Subscription::into_shared`] is called.
+pub struct SharedSubscription<M: Message> {
+    sub: Arc<Subscription<M>>,
+}
+
+impl<M: Message> SharedSubscription<M> {
+    /// Creates a new [`SharedSubscription`] which is registered in the given message broker.
+    pub fn new(msg_broker: Arc<dyn MessageBroker>) -> Self {
+        Subscription::new(msg_broker).into_shared()
+    }
+}
+
+impl<M: Message> Clone for SharedSubscription<M> {
+    fn clone(&self) -> Self {
+        Self {
+            sub: Arc::clone(&self.sub),
+        }
+    }
+}
+
+impl<M: Message> Deref for SharedSubscription<M> {
+    type Target = Subscription<M>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.sub
+    }
+}
+
+impl<M: Message> From<Subscription<M>> for SharedSubscription<M> {
+    fn from(sub: Subscription<M>) -> Self {
+        sub.into_shared()
+    }
+}
+
+/// Defines the order in which a [`MultiSubscription`] receives messages
+/// from the subscriptions it consists of.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ReceiveStrategy {
+    /// Messages are received from the first subscription which has any,
+    /// in the order the subscriptions were added.
+    #[default]
+    Sequential,
+    /// Subscriptions take turns, one message each.
+    RoundRobin,
+    /// Subscriptions take turns, each one receiving up to its weight
+    /// (see [`MultiSubscription::set_weight`]) messages in a row.
+    Weighted,
+    /// Messages are received in the order they were published, which is defined
+    /// by their sequence numbers (see [`MessageBroker::next_sequence_number`]).
+    PublishOrder,
+}
+
+// A subscription to messages of one type which is a part of [`MultiSubscription`].
+struct MultiSubscriptionEntry {
+    msg_type_id: MessageTypeId,
+    sub: Box<dyn ErasedSubscription + Send + Sync>,
+    weight: usize,
+}
+
+/// A type which is used for receiving messages of multiple types from the message broker.
 pub struct MultiSubscription {
-    msg_broker: Option<Arc<dyn MessageBroker>>,
+    msg_broker: Option<BrokerRef>,
     is_active: AtomicBool,
-    subs: Vec<Box<dyn ErasedSubscription>>,
+    is_paused: AtomicBool,
+    pause_capacity: AtomicUsize,
+    waker: Mutex<Option<Waker>>,
+    panic_policy: PanicPolicy,
+    recv_strategy: ReceiveStrategy,
+    subs: Vec<MultiSubscriptionEntry>,
+    // The index of the subscription whose turn it is and the number of messages
+    // which it has already received in a row.
+    turn: AtomicUsize,
+    turn_received: AtomicUsize,
 }
 
 impl MultiSubscription {
+    /// Creates a new [`MultiSubscription`] which is not registered in any message broker
+    /// and therefore can't be used for receiving messages.
     pub fn unregistered() -> Self {
         Self {
             msg_broker: None,
             is_active: AtomicBool::new(true),
+            is_paused: AtomicBool::new(false),
+            pause_capacity: AtomicUsize::new(usize::MAX),
+            waker: Mutex::new(None),
+            panic_policy: PanicPolicy::default(),
+            recv_strategy: ReceiveStrategy::default(),
             subs: Vec::new(),
+            turn: AtomicUsize::new(0),
+            turn_received: AtomicUsize::new(0),
         }
     }
 
+    /// Creates a new [`MultiSubscription`] which is registered in the ambient message broker,
+    /// so subscriptions which are added to it are registered there too.
+    ///
+    /// See [`current_broker`]
+    pub fn ambient() -> Self {
+        let mut multi_sub = Self::unregistered();
+        let _ = multi_sub.register(current_broker());
+
+        multi_sub
+    }
+
+    /// Adds a subscription to messages of the given type.
+    ///
+    /// If the [`MultiSubscription`] is registered, the new subscription is registered
+    /// in the same message broker.
+    ///
+    /// If messages of this type are already covered, nothing happens and the existing
+    /// subscription keeps its state and pending messages.
+    /// Use [`MultiSubscription::try_add`] to find out about it.
     pub fn add<M: Message>(&mut self) -> &mut Self {
-        let new_sub: Subscription<M> = if let Some(msg_broker) = self.msg_broker.clone() {
-            Subscription::new(msg_broker)
-        } else {
-            Subscription::unregistered()
-        };
-        self.subs.push(Box::new(new_sub));
+        if self.contains::<M>() {
+            return self;
+        }
+
+        let mut new_sub: Subscription<M> = Subscription::unregistered();
+        if let Some(ref broker_ref) = self.msg_broker {
+            if let Some(msg_broker) = broker_ref.upgrade() {
+                let _ = new_sub.register_with(&msg_broker, broker_ref.is_weak());
+            }
+        }
+        self.apply_state(&new_sub);
+        self.subs.push(MultiSubscriptionEntry {
+            msg_type_id: MessageTypeId::of::<M>(),
+            sub: Box::new(new_sub),
+            weight: 1,
+        });
 
         self
     }
+
+    /// Adds a subscription to messages of the given type like [`MultiSubscription::add`],
+    /// but fails if messages of this type are already covered.
+    pub fn try_add<M: Message>(&mut self) -> Result<&mut Self, SubscriptionError> {
+        if self.contains::<M>() {
+            return Err(SubscriptionError::MessageTypeAlreadyAdded {
+                msg_type_name: std::any::type_name::<M>(),
+            });
+        }
+
+        Ok(self.add::<M>())
+    }
+
+    /// Removes the subscription to messages of the given type and destroys its message channel.
+    ///
+    /// Pending messages of this type are dropped. It fails only if messages of the given type
+    /// aren't covered by the subscription.
+    pub fn remove<M: Message>(&mut self) -> Result<(), SubscriptionError> {
+        let idx = self.entry_index(MessageTypeId::of::<M>()).ok_or(
+            SubscriptionError::MessageTypeNotFound {
+                msg_type_name: std::any::type_name::<M>(),
+            },
+        )?;
+        let mut entry = self.subs.remove(idx);
+
+        let turn = self.turn.get_mut();
+        if idx < *turn {
+            *turn -= 1;
+        } else if idx == *turn {
+            *self.turn_received.get_mut() = 0;
+        }
+        if *turn >= self.subs.len() {
+            *turn = 0;
+        }
+
+        // If the message broker is gone, nothing is sent to the channel anymore,
+        // so the subscription is removed anyway.
+        if entry.sub.is_registered() {
+            let _ = entry.sub.unregister();
+        }
+
+        Ok(())
+    }
+
+    /// Returns if messages of the given type are covered by the subscription.
+    pub fn contains<M: Message>(&self) -> bool {
+        self.entry_index(MessageTypeId::of::<M>()).is_some()
+    }
+
+    /// Returns an iterator over type ids of all message types covered by the subscription
+    /// in the order they were added.
+    pub fn message_type_ids(&self) -> impl Iterator<Item = MessageTypeId> + '_ {
+        self.subs.iter().map(|entry| entry.msg_type_id)
+    }
+
+    /// Returns if the subscription to messages of the given type is active.
+    pub fn is_active_type<M: Message>(&self) -> bool {
+        self.entry::<M>().is_ok_and(|entry| entry.sub.is_active())
+    }
+
+    /// Activates the subscription to messages of the given type if it was deactivated before.
+    pub fn activate_type<M: Message>(&self) -> Result<(), SubscriptionError> {
+        self.entry::<M>()?.sub.activate()
+    }
+
+    /// Dectivates the subscription to messages of the given type, while messages
+    /// of other types are still received.
+    pub fn deactivate_type<M: Message>(&self) -> Result<(), SubscriptionError> {
+        self.entry::<M>()?.sub.deactivate()
+    }
+
+    fn register_with(
+        &mut self,
+        msg_broker: &Arc<dyn MessageBroker>,
+        is_weak: bool,
+    ) -> Result<(), SubscriptionError> {
+        if self.is_registered() {
+            return Err(SubscriptionError::AlreadyRegistered);
+        }
+
+        for idx in 0..self.subs.len() {
+            let sub = &mut self.subs[idx].sub;
+            let res = if is_weak {
+                sub.register_weak(msg_broker)
+            } else {
+                sub.register(Arc::clone(msg_broker))
+            };
+            if let Err(sub_err) = res {
+                self.subs[..idx].iter_mut().for_each(|entry| {
+                    let _ = entry.sub.unregister();
+                });
+
+                return Err(sub_err);
+            }
+        }
+
+        self.subs
+            .iter()
+            .for_each(|entry| self.apply_state(entry.sub.as_ref()));
+        self.msg_broker = Some(BrokerRef::new(msg_broker, is_weak));
+
+        Ok(())
+    }
+
+    // Makes the newly registered subscription deactivated or paused
+    // if the whole [`MultiSubscription`] is and passes the waker to it.
+    fn apply_state(&self, sub: &dyn ErasedSubscription) {
+        sub.set_waker(util::lock(&self.waker).clone());
+        if !self.is_active() {
+            let _ = sub.deactivate();
+        }
+        if self.is_paused() {
+            let _ = sub.pause(Some(self.pause_capacity.load(Ordering::SeqCst)));
+        }
+    }
+
+    // Returns the index of the subscription to messages with the given type id.
+    fn entry_index(&self, msg_type_id: MessageTypeId) -> Option<usize> {
+        self.subs
+            .iter()
+            .position(|entry| entry.msg_type_id == msg_type_id)
+    }
+
+    // Returns the subscription to messages of the given type.
+    fn entry<M: Message>(&self) -> Result<&MultiSubscriptionEntry, SubscriptionError> {
+        self.entry_index(MessageTypeId::of::<M>())
+            .map(|idx| &self.subs[idx])
+            .ok_or(SubscriptionError::MessageTypeNotFound {
+                msg_type_name: std::any::type_name::<M>(),
+            })
+    }
+
+    /// Returns the order in which messages are received.
+    pub fn receive_strategy(&self) -> ReceiveStrategy {
+        self.recv_strategy
+    }
+
+    /// Sets the order in which messages are received.
+    ///
+    /// The turns start over from the first subscription.
+    pub fn set_receive_strategy(&mut self, recv_strategy: ReceiveStrategy) -> &mut Self {
+        self.recv_strategy = recv_strategy;
+        *self.turn.get_mut() = 0;
+        *self.turn_received.get_mut() = 0;
+
+        self
+    }
+
+    /// Sets how many messages of the given type can be received in a row
+    /// when [`ReceiveStrategy::Weighted`] is used.
+    ///
+    /// The weight of each message type is 1 by default, zero weights are treated as 1.
+    pub fn set_weight<M: Message>(&mut self, weight: usize) -> &mut Self {
+        self.subs
+            .iter_mut()
+            .filter(|entry| entry.msg_type_id == MessageTypeId::of::<M>())
+            .for_each(|entry| entry.weight = weight.max(1));
+
+        self
+    }
+
+    // Receives a message from the subscriptions taking turns, each one receiving
+    // up to `weight` messages in a row.
+    fn recv_message_in_turns(
+        &self,
+        weight: impl Fn(&MultiSubscriptionEntry) -> usize,
+    ) -> Option<Arc<dyn Message>> {
+        let subs_count = self.subs.len();
+        let turn = self.turn.load(Ordering::SeqCst);
+        for offset in 0..subs_count {
+            let idx = (turn + offset) % subs_count;
+            let entry = &self.subs[idx];
+            let Some(msg) = entry.sub.recv_message() else {
+                continue;
+            };
+
+            let received = if offset == 0 {
+                self.turn_received.load(Ordering::SeqCst) + 1
+            } else {
+                1
+            };
+            if received >= weight(entry) {
+                self.turn.store((idx + 1) % subs_count, Ordering::SeqCst);
+                self.turn_received.store(0, Ordering::SeqCst);
+            } else {
+                self.turn.store(idx, Ordering::SeqCst);
+                self.turn_received.store(received, Ordering::SeqCst);
+            }
+
+            return Some(msg);
+        }
+
+        None
+    }
 }
 
 impl ErasedSubscription for MultiSubscription {
     fn message_broker(&self) -> Option<Arc<dyn MessageBroker>> {
-        self.msg_broker.clone()
+        self.msg_broker.as_ref()?.upgrade()
     }
 
     fn is_registered(&self) -> bool {
         self.msg_broker.is_some()
     }
 
+    fn is_broker_gone(&self) -> bool {
+        self.msg_broker
+            .as_ref()
+            .is_some_and(|msg_broker| msg_broker.is_gone())
+    }
+
     fn is_active(&self) -> bool {
         self.is_active.load(Ordering::SeqCst)
     }
 
+    /// Registers all subscriptions in the given message broker.
+    ///
+    /// Either all subscriptions are registered or, if one of them fails,
+    /// the ones which were already registered are unregistered again.
     fn register(&mut self, msg_broker: Arc<dyn MessageBroker>) -> Result<(), SubscriptionError> {
-        self.subs
-            .iter_mut()
-            .try_for_each(|sub| sub.register(Arc::clone(&msg_broker)))
+        self.register_with(&msg_broker, false)
     }
 
+    /// Registers all subscriptions in the given message broker, but keeps only
+    /// weak references to it.
+    ///
+    /// See [`ErasedSubscription::register_weak`]
+    fn register_weak(
+        &mut self,
+        msg_broker: &Arc<dyn MessageBroker>,
+    ) -> Result<(), SubscriptionError> {
+        self.register_with(msg_broker, true)
+    }
+
+    /// Unregisters all subscriptions.
+    ///
+    /// All subscriptions are unregistered even if some of them fail, in that case
+    /// the first error is returned.
     fn unregister(&mut self) -> Result<(), SubscriptionError> {
-        self.subs.iter_mut().try_for_each(|sub| sub.unregister())
+        if self.msg_broker.take().is_none() {
+            return Err(SubscriptionError::NotRegistered);
+        }
+
+        self.subs
+            .iter_mut()
+            .filter(|entry| entry.sub.is_registered())
+            .map(|entry| entry.sub.unregister())
+            .fold(Ok(()), Result::and)
     }
 
     fn activate(&self) -> Result<(), SubscriptionError> {
         self.is_active.store(true, Ordering::SeqCst);
-        self.subs.iter().try_for_each(|sub| sub.activate())
+        self.subs.iter().try_for_each(|entry| entry.sub.activate())
     }
 
     fn deactivate(&self) -> Result<(), SubscriptionError> {
         self.is_active.store(false, Ordering::SeqCst);
-        self.subs.iter().try_for_each(|sub| sub.deactivate())
+        self.subs
+            .iter()
+            .try_for_each(|entry| entry.sub.deactivate())
+    }
+
+    fn is_paused(&self) -> bool {
+        self.is_paused.load(Ordering::SeqCst)
+    }
+
+    fn pause(&self, capacity: Option<usize>) -> Result<(), SubscriptionError> {
+        self.is_paused.store(true, Ordering::SeqCst);
+        self.pause_capacity
+            .store(capacity.unwrap_or(usize::MAX), Ordering::SeqCst);
+        self.subs
+            .iter()
+            .try_for_each(|entry| entry.sub.pause(capacity))
+    }
+
+    fn resume(&self) -> Result<(), SubscriptionError> {
+        self.is_paused.store(false, Ordering::SeqCst);
+        self.subs.iter().try_for_each(|entry| entry.sub.resume())
+    }
+
+    fn set_waker(&self, waker: Option<Waker>) {
+        self.subs
+            .iter()
+            .for_each(|entry| entry.sub.set_waker(waker.clone()));
+        *util::lock(&self.waker) = waker;
+    }
+
+    fn panic_policy(&self) -> PanicPolicy {
+        self.panic_policy
+    }
+
+    fn set_panic_policy(&mut self, panic_policy: PanicPolicy) {
+        self.panic_policy = panic_policy;
+    }
+
+    fn is_closed(&self) -> bool {
+        !self.subs.is_empty() && self.subs.iter().all(|entry| entry.sub.is_closed())
     }
 
     fn recv_message(&self) -> Option<Arc<dyn Message>> {
-        for sub in &self.subs {
-            if let Some(msg) = sub.recv_message() {
-                return Some(msg);
+        match self.recv_strategy {
+            ReceiveStrategy::Sequential => {
+                self.subs.iter().find_map(|entry| entry.sub.recv_message())
             }
+            ReceiveStrategy::RoundRobin => self.recv_message_in_turns(|_| 1),
+            ReceiveStrategy::Weighted => self.recv_message_in_turns(|entry| entry.weight),
+            ReceiveStrategy::PublishOrder => loop {
+                let (seq, entry) = self
+                    .subs
+                    .iter()
+                    .filter_map(|entry| Some((entry.sub.peek_sequence_number()?, entry)))
+                    .min_by_key(|(seq, _)| *seq)?;
+
+                // The peeked message could be taken by another receiver in the meantime,
+                // then the next one is looked for.
+                if let Some(msg) = entry.sub.recv_sequenced_message(seq) {
+                    return Some(msg);
+                }
+            },
         }
+    }
 
-        None
+    fn pending_messages(&self) -> usize {
+        self.subs
+            .iter()
+            .map(|entry| entry.sub.pending_messages())
+            .sum()
+    }
+
+    fn expired_messages(&self) -> usize {
+        self.subs
+            .iter()
+            .map(|entry| entry.sub.expired_messages())
+            .sum()
+    }
+
+    fn peek_sequence_number(&self) -> Option<u64> {
+        self.subs
+            .iter()
+            .filter_map(|entry| entry.sub.peek_sequence_number())
+            .min()
+    }
+
+    fn recv_sequenced_message(&self, seq: u64) -> Option<Arc<dyn Message>> {
+        self.subs
+            .iter()
+            .find_map(|entry| entry.sub.recv_sequenced_message(seq))
     }
 
     fn message_iter(&self) -> MessageIter<'_> {
@@ -230,8 +1003,64 @@ impl ErasedSubscription for MultiSubscription {
     }
 
     fn process_messages<'f>(&self, mut f: Box<dyn ErasedMessageHandler + 'f>) {
-        while let Some(msg) = self.recv_message() {
-            let _ = f.call(msg);
+        process_messages_while(self, &mut f, |_| true);
+    }
+
+    fn process_messages_max<'f>(
+        &self,
+        max: usize,
+        mut f: Box<dyn ErasedMessageHandler + 'f>,
+    ) -> ProcessedMessages {
+        process_messages_while(self, &mut f, |handled| handled < max)
+    }
+
+    fn process_messages_for<'f>(
+        &self,
+        budget: Duration,
+        mut f: Box<dyn ErasedMessageHandler + 'f>,
+    ) -> ProcessedMessages {
+        let start = Instant::now();
+        process_messages_while(self, &mut f, |_| start.elapsed() < budget)
+    }
+}
+
+// Processes messages received from the given subscription while `proceed` returns true
+// for the number of already handled messages.
+fn process_messages_while(
+    sub: &dyn ErasedSubscription,
+    f: &mut dyn ErasedMessageHandler,
+    mut proceed: impl FnMut(usize) -> bool,
+) -> ProcessedMessages {
+    let mut handled = 0;
+    while proceed(handled) {
+        let Some(msg) = sub.recv_message() else { break };
+        handle_message(sub, f, msg);
+        handled += 1;
+    }
+
+    ProcessedMessages {
+        handled,
+        has_more: sub.pending_messages() > 0,
+    }
+}
+
+// Handles the message received from the given subscription and passes it
+// to the dead-letter queue if the handler fails.
+//
+// Messages which the handler doesn't accept aren't failures, since a [`MultiSubscription`]
+// yields messages of all its types to every handler.
+fn handle_message(
+    sub: &dyn ErasedSubscription,
+    f: &mut dyn ErasedMessageHandler,
+    msg: Arc<dyn Message>,
+) {
+    match sub.panic_policy().call(f, Arc::clone(&msg)) {
+        Ok(()) | Err(MessageHandlerError::WrongMessageType { .. }) => {}
+        Err(handler_err) => {
+            if let Some(msg_broker) = sub.message_broker() {
+                let dead_letter = DeadLetter::new(msg, DeadLetterReason::HandlerFailed);
+                msg_broker.dead_letter(dead_letter.with_handler_error(handler_err));
+            }
         }
     }
 }
@@ -240,8 +1069,72 @@ impl ErasedSubscription for MultiSubscription {
 pub enum SubscriptionError {
     AlreadyRegistered,
     NotRegistered,
+    /// The subscription holds a weak reference to its message broker which was dropped.
+    BrokerGone,
+    /// The [`MultiSubscription`] doesn't cover messages of the given type.
+    MessageTypeNotFound {
+        msg_type_name: &'static str,
+    },
+    /// The [`MultiSubscription`] already covers messages of the given type.
+    MessageTypeAlreadyAdded {
+        msg_type_name: &'static str,
+    },
+}
+
+impl fmt::Display for SubscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscriptionError::AlreadyRegistered => {
+                write!(f, "the subscription is already registered")
+            }
+            SubscriptionError::NotRegistered => write!(f, "the subscription isn't registered"),
+            SubscriptionError::BrokerGone => {
+                write!(f, "the message broker of the subscription was dropped")
+            }
+            SubscriptionError::MessageTypeNotFound { msg_type_name } => write!(
+                f,
+                "the subscription doesn't cover messages of type `{}`",
+                msg_type_name
+            ),
+            SubscriptionError::MessageTypeAlreadyAdded { msg_type_name } => write!(
+                f,
+                "the subscription already covers messages of type `{}`",
+                msg_type_name
+            ),
+        }
+    }
+}
+
+impl Error for SubscriptionError {}
+
+/// The reason why [`ErasedSubscription::try_recv_message`] didn't receive a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TryRecvError {
+    /// No message is pending right now.
+    Empty,
+    /// The message broker was shut down and no messages are left.
+    Closed,
+    /// The subscription isn't registered in any message broker.
+    NotRegistered,
+    /// The subscription holds a weak reference to its message broker which was dropped.
+    BrokerGone,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "no message is pending"),
+            TryRecvError::Closed => write!(f, "the subscription is closed"),
+            TryRecvError::NotRegistered => write!(f, "the subscription isn't registered"),
+            TryRecvError::BrokerGone => {
+                write!(f, "the message broker of the subscription was dropped")
+            }
+        }
+    }
 }
 
+impl Error for TryRecvError {}
+
 mod sealed {
     #[doc(hidden)]
     pub trait Sealed {}
@@ -249,3 +1142,36 @@ mod sealed {
     impl<M: crate::Message> Sealed for crate::Subscription<M> {}
     impl Sealed for crate::MultiSubscription {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestMsg0;
+
+    impl Message for TestMsg0 {}
+
+    struct TestMsg1;
+
+    impl Message for TestMsg1 {}
+
+    #[test]
+    fn test_multi_subscription_register_rolls_back() {
+        let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+        let mut multi_sub = MultiSubscription::unregistered();
+        multi_sub.add::<TestMsg0>().add::<TestMsg1>();
+        // The second subscription is already registered, so registering fails mid-way.
+        let _ = multi_sub.subs[1].sub.register(Arc::clone(&broker));
+
+        assert!(matches!(
+            multi_sub.register(Arc::clone(&broker)),
+            Err(SubscriptionError::AlreadyRegistered)
+        ));
+        assert!(!multi_sub.is_registered());
+        assert!(!multi_sub.subs[0].sub.is_registered());
+
+        let _ = broker.publish_value(TestMsg0);
+        assert!(multi_sub.recv_message().is_none());
+    }
+}
@@ -1,18 +1,288 @@
-use crate::{channel::*, *};
+use crate::{
+    callback,
+    callback::{CallbackHandler, SyncCallback},
+    channel::*,
+    *,
+};
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+/// The way messages are distributed between members of a [`QueueGroup`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum QueueGroupStrategy {
+    /// Members receive messages in turns.
+    #[default]
+    RoundRobin,
+    /// Each message is received by the member with the fewest pending messages.
+    LeastLoaded,
+}
+
+/// A named group of subscriptions to messages of the same type which share
+/// the published messages between each other.
+///
+/// Each message is received by only one member of the group, while subscriptions
+/// which aren't members of any group still receive every message.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueueGroup {
+    name: String,
+    strategy: QueueGroupStrategy,
+}
+
+impl QueueGroup {
+    /// Creates a new [`QueueGroup`] with the given name which distributes messages
+    /// in turns.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            strategy: QueueGroupStrategy::default(),
+        }
+    }
+
+    /// Sets the way messages are distributed between members of the group.
+    ///
+    /// The strategy is defined by the first subscription which joins the group.
+    pub fn with_strategy(mut self, strategy: QueueGroupStrategy) -> Self {
+        self.strategy = strategy;
+
+        self
+    }
+
+    /// Returns the name of the group.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the way messages are distributed between members of the group.
+    pub fn strategy(&self) -> QueueGroupStrategy {
+        self.strategy
+    }
+}
+
+/// The way a [`MessageTopic`] delivers messages to callbacks registered with
+/// [`MessageBroker::on`](MessageBroker#method.on).
+///
+/// Subscriptions and callbacks which run on a [`Dispatcher`] always receive messages
+/// through their queues.
+///
+/// # Ordering
+///
+/// With [`DeliveryMode::Queued`] each callback receives messages in the order of their
+/// sequence numbers, but a message published while the callback is running on another
+/// thread is handled by that thread, so `publish_message` may return before the callback
+/// handles the message.
+///
+/// With [`DeliveryMode::Direct`] each callback is invoked on the publishing thread before
+/// `publish_message` returns. Messages published concurrently by different threads reach
+/// a callback in the order the threads get to it, which may differ from the order of their
+/// sequence numbers. Messages which callbacks publish themselves are delivered after
+/// the outermost callback returns, in the order they were published, so callbacks are never
+/// invoked re-entrantly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DeliveryMode {
+    /// Messages are put into the queues of callbacks, which are drained right after
+    /// the message is sent.
+    #[default]
+    Queued,
+    /// Callbacks are invoked directly on the publishing thread without queueing messages.
+    Direct,
+}
+
+/// Defines what happens with messages whose time to live runs out before they are received.
+///
+/// See [`Message::ttl`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ExpiryPolicy {
+    /// Expired messages are dropped.
+    #[default]
+    Discard,
+    /// Expired messages are passed to [`MessageBroker::dead_letter`] by the subscription
+    /// which skipped them.
+    DeadLetter,
+}
+
+/// The window in which a [`MessageTopic`] remembers the ids of published messages
+/// to drop their duplicates.
+///
+/// See [`Message::message_id`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DedupWindow {
+    /// The ids of the given number of the latest messages are remembered.
+    Count(usize),
+    /// The id of each message is remembered for the given time after it is published.
+    Duration(Duration),
+}
+
+/// The number of messages checked for duplicates by a [`MessageTopic`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DedupStats {
+    /// The number of messages which were sent.
+    pub unique: u64,
+    /// The number of messages which were dropped as duplicates.
+    pub duplicates: u64,
+}
+
+// Remembers the ids of messages in the dedup window.
+struct Deduplicator {
+    window: DedupWindow,
+    ids: HashSet<u64>,
+    // The remembered ids with the time they were published, the oldest one is the first.
+    history: VecDeque<(u64, Instant)>,
+}
+
+impl Deduplicator {
+    fn new(window: DedupWindow) -> Self {
+        Self {
+            window,
+            ids: HashSet::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    // Returns if the given id is remembered at the time `now`, forgetting the ids
+    // which have left the window first.
+    fn contains(&mut self, id: u64, now: Instant) -> bool {
+        if let DedupWindow::Duration(duration) = self.window {
+            while self
+                .history
+                .front()
+                .is_some_and(|&(_, published_at)| now.duration_since(published_at) >= duration)
+            {
+                self.forget_oldest();
+            }
+        }
+
+        self.ids.contains(&id)
+    }
+
+    // Remembers the given id published at the time `now` if it isn't remembered yet.
+    fn insert(&mut self, id: u64, now: Instant) {
+        if !self.ids.insert(id) {
+            return;
+        }
+        self.history.push_back((id, now));
+
+        if let DedupWindow::Count(count) = self.window {
+            while self.history.len() > count {
+                self.forget_oldest();
+            }
+        }
+    }
+
+    fn forget_oldest(&mut self) {
+        if let Some((id, _)) = self.history.pop_front() {
+            self.ids.remove(&id);
+        }
+    }
+}
+
+// Message channels which are members of the same queue group.
+struct QueueGroupSenders {
+    strategy: QueueGroupStrategy,
+    msg_senders: Vec<MessageSender>,
+    // The index of the member whose turn it is to receive a message.
+    turn: usize,
+}
+
+impl QueueGroupSenders {
+    // Sends the given message to one active member of the group.
+    //
+    // Members are tried in the order of the strategy until one of them accepts the message,
+    // if all of them reject it, the error of the first one is returned.
+    // Returns `None` if there is no active member.
+    fn send(
+        &mut self,
+        msg: Arc<dyn Message>,
+        seq: u64,
+        expiry: Option<Expiry>,
+    ) -> Option<Result<(), MessageChannelError>> {
+        let members_count = self.msg_senders.len();
+        let mut candidates: Vec<usize> = (0..members_count)
+            .map(|offset| (self.turn + offset) % members_count)
+            .filter(|&idx| self.msg_senders[idx].is_active())
+            .collect();
+        if self.strategy == QueueGroupStrategy::LeastLoaded {
+            // Paused members don't process messages, so they are tried last.
+            // Ties are broken by the turn, since the sort is stable.
+            candidates.sort_by_key(|&idx| {
+                let msg_send = &self.msg_senders[idx];
+                (msg_send.is_paused(), msg_send.pending())
+            });
+        }
+
+        let mut first_err = None;
+        for idx in candidates {
+            match self.msg_senders[idx].send(Arc::clone(&msg), seq, expiry) {
+                Ok(()) => {
+                    self.turn = (idx + 1) % members_count;
+                    return Some(Ok(()));
+                }
+                Err(msg_channel_err) => {
+                    first_err.get_or_insert(msg_channel_err);
+                }
+            }
+        }
+
+        first_err.map(Err)
+    }
+}
+
+// The outcome of sending a message to the channels of a topic.
+#[derive(Default)]
+pub(crate) struct Delivery {
+    // The number of channels which received the message.
+    pub(crate) received: usize,
+    // The errors of the channels which rejected the message, one per channel.
+    pub(crate) rejections: Vec<MessageChannelError>,
+    // Whether the message was dropped as a duplicate.
+    pub(crate) is_duplicate: bool,
+}
+
+struct MessageTopicState {
+    msg_senders: HashMap<MessageChannelId, MessageSender>,
+    queue_groups: HashMap<String, QueueGroupSenders>,
+    // Callbacks which receive messages through the channels in `msg_senders`.
+    callbacks: HashMap<MessageChannelId, Arc<SyncCallback>>,
+    delivery_mode: DeliveryMode,
+    ttl: Option<Duration>,
+    expiry_policy: ExpiryPolicy,
+    deduplicator: Option<Deduplicator>,
+    dedup_stats: DedupStats,
+    is_closed: bool,
+}
 
 pub struct MessageTopic {
     msg_type_id: MessageTypeId,
-    msg_senders: Mutex<HashMap<MessageChannelId, MessageSender>>,
+    state: Mutex<MessageTopicState>,
+    // The source of the time for message expiry and the dedup window.
+    clock: Arc<dyn Clock>,
 }
 
 impl MessageTopic {
     pub fn new(msg_type_id: MessageTypeId) -> Self {
+        Self::with_clock(msg_type_id, Arc::new(SystemClock))
+    }
+
+    /// Creates a new [`MessageTopic`] which expires messages and forgets
+    /// the ids in its dedup window according to the given [`Clock`].
+    pub fn with_clock(msg_type_id: MessageTypeId, clock: Arc<dyn Clock>) -> Self {
         Self {
             msg_type_id,
-            msg_senders: Mutex::new(HashMap::new()),
+            clock,
+            state: Mutex::new(MessageTopicState {
+                msg_senders: HashMap::new(),
+                queue_groups: HashMap::new(),
+                callbacks: HashMap::new(),
+                delivery_mode: DeliveryMode::default(),
+                ttl: None,
+                expiry_policy: ExpiryPolicy::default(),
+                deduplicator: None,
+                dedup_stats: DedupStats::default(),
+                is_closed: false,
+            }),
         }
     }
 
@@ -20,13 +290,94 @@ impl MessageTopic {
         Self::new(MessageTypeId::of::<M>())
     }
 
-    pub(crate) fn create_message_channel(&self) -> MessageReceiver {
-        let (msg_send, msg_recv) = message_channel_new(self.msg_type_id);
-        let mut msg_senders = self
-            .msg_senders
-            .lock()
-            .expect("The message topic is poisoned");
-        msg_senders.insert(msg_send.channel_id(), msg_send);
+    /// Returns the way the topic delivers messages to callbacks.
+    pub fn delivery_mode(&self) -> DeliveryMode {
+        util::lock(&self.state).delivery_mode
+    }
+
+    /// Sets the way the topic delivers messages to callbacks.
+    pub fn set_delivery_mode(&self, delivery_mode: DeliveryMode) {
+        util::lock(&self.state).delivery_mode = delivery_mode;
+    }
+
+    /// Returns how long messages of the topic stay relevant after they are published,
+    /// unless the message defines its own [`Message::ttl`].
+    pub fn ttl(&self) -> Option<Duration> {
+        util::lock(&self.state).ttl
+    }
+
+    /// Sets how long messages of the topic stay relevant after they are published.
+    ///
+    /// It applies only to messages which are published afterwards.
+    pub fn set_ttl(&self, ttl: Option<Duration>) {
+        util::lock(&self.state).ttl = ttl;
+    }
+
+    /// Returns what happens with expired messages of the topic.
+    pub fn expiry_policy(&self) -> ExpiryPolicy {
+        util::lock(&self.state).expiry_policy
+    }
+
+    /// Sets what happens with expired messages of the topic.
+    ///
+    /// It applies only to messages which are published afterwards.
+    pub fn set_expiry_policy(&self, expiry_policy: ExpiryPolicy) {
+        util::lock(&self.state).expiry_policy = expiry_policy;
+    }
+
+    /// Returns the window in which the topic drops duplicates of messages,
+    /// `None` if duplicates aren't dropped.
+    pub fn dedup_window(&self) -> Option<DedupWindow> {
+        util::lock(&self.state)
+            .deduplicator
+            .as_ref()
+            .map(|deduplicator| deduplicator.window)
+    }
+
+    /// Makes the topic drop messages whose [`Message::message_id`] was already published
+    /// within the given window, or stop dropping duplicates if it is `None`.
+    ///
+    /// Duplicates are dropped before they are sent to any channel or callback,
+    /// and publishing them succeeds. The ids which were remembered before are forgotten.
+    pub fn set_dedup_window(&self, dedup_window: Option<DedupWindow>) {
+        util::lock(&self.state).deduplicator = dedup_window.map(Deduplicator::new);
+    }
+
+    /// Returns the number of messages which were checked for duplicates.
+    pub fn dedup_stats(&self) -> DedupStats {
+        util::lock(&self.state).dedup_stats
+    }
+
+    // Creates a new message channel which receives every message if `queue_group` is `None`,
+    // or shares messages with other members of the given queue group otherwise.
+    //
+    // Expired messages are passed to `dead_letter_sink`, see [`message_channel_new`].
+    pub(crate) fn create_message_channel(
+        &self,
+        queue_group: Option<&QueueGroup>,
+        dead_letter_sink: Option<Weak<dyn MessageBroker>>,
+    ) -> MessageReceiver {
+        let (msg_send, msg_recv) =
+            message_channel_new(self.msg_type_id, dead_letter_sink, Arc::clone(&self.clock));
+        let mut state = util::lock(&self.state);
+        if state.is_closed {
+            msg_send.close(false);
+        }
+        match queue_group {
+            Some(queue_group) => state
+                .queue_groups
+                .entry(queue_group.name().to_string())
+                .or_insert_with(|| QueueGroupSenders {
+                    strategy: queue_group.strategy(),
+                    msg_senders: Vec::new(),
+                    turn: 0,
+                })
+                .msg_senders
+                .push(msg_send),
+            None => {
+                state.msg_senders.insert(msg_send.channel_id(), msg_send);
+            }
+        }
 
         msg_recv
     }
@@ -35,31 +386,357 @@ impl MessageTopic {
         &self,
         msg_recv: MessageReceiver,
     ) -> Result<(), MessageTopicError> {
-        let mut msg_senders = self
+        let channel_id = msg_recv.channel_id();
+        let mut state = util::lock(&self.state);
+        if state.msg_senders.remove(&channel_id).is_some() {
+            return Ok(());
+        }
+
+        let (name, queue_group) = state
+            .queue_groups
+            .iter_mut()
+            .find(|(_, queue_group)| {
+                queue_group
+                    .msg_senders
+                    .iter()
+                    .any(|msg_send| msg_send.channel_id() == channel_id)
+            })
+            .ok_or(MessageTopicError::ChannelNotFound)?;
+        queue_group
             .msg_senders
-            .lock()
-            .expect("The message topic is poisoned");
-        msg_senders
-            .remove(&msg_recv.channel_id())
+            .retain(|msg_send| msg_send.channel_id() != channel_id);
+        if queue_group.msg_senders.is_empty() {
+            let name = name.clone();
+            state.queue_groups.remove(&name);
+        } else {
+            queue_group.turn %= queue_group.msg_senders.len();
+        }
+
+        Ok(())
+    }
+
+    // Creates a new message channel which passes every message to the given handler
+    // right after it is sent.
+    pub(crate) fn add_callback(
+        &self,
+        handler: CallbackHandler,
+        dead_letter_sink: Option<Weak<dyn MessageBroker>>,
+    ) -> MessageChannelId {
+        let (msg_send, msg_recv) =
+            message_channel_new(self.msg_type_id, dead_letter_sink, Arc::clone(&self.clock));
+        let channel_id = msg_send.channel_id();
+
+        let mut state = util::lock(&self.state);
+        state.msg_senders.insert(channel_id, msg_send);
+        state
+            .callbacks
+            .insert(channel_id, Arc::new(SyncCallback::new(msg_recv, handler)));
+
+        channel_id
+    }
+
+    pub(crate) fn remove_callback(
+        &self,
+        channel_id: MessageChannelId,
+    ) -> Result<(), MessageTopicError> {
+        let mut state = util::lock(&self.state);
+        state.msg_senders.remove(&channel_id);
+        state
+            .callbacks
+            .remove(&channel_id)
             .map(|_| ())
             .ok_or(MessageTopicError::ChannelNotFound)
     }
 
-    pub(crate) fn send_message(&self, msg: Arc<dyn Message>) -> Result<(), MessageTopicError> {
-        let msg_senders = self
+    // Returns if the topic has no message channels and uses the default configuration,
+    // so it can be created again without losing anything.
+    pub(crate) fn is_unused(&self) -> bool {
+        let state = util::lock(&self.state);
+
+        state.msg_senders.is_empty()
+            && state.queue_groups.is_empty()
+            && state.callbacks.is_empty()
+            && state.delivery_mode == DeliveryMode::default()
+            && state.ttl.is_none()
+            && state.expiry_policy == ExpiryPolicy::default()
+            && state.deduplicator.is_none()
+    }
+
+    /// Returns if the topic is closed, so it rejects all messages.
+    pub fn is_closed(&self) -> bool {
+        util::lock(&self.state).is_closed
+    }
+
+    // Closes the topic and all its message channels, so the topic rejects new messages
+    // and subscriptions know they won't receive them.
+    pub(crate) fn close(&self, mode: ShutdownMode) -> ShutdownSummary {
+        let mut state = util::lock(&self.state);
+        state.is_closed = true;
+
+        let state = &*state;
+        let msg_senders = state.msg_senders.values().chain(
+            state
+                .queue_groups
+                .values()
+                .flat_map(|queue_group| queue_group.msg_senders.iter()),
+        );
+
+        let discard = mode == ShutdownMode::Discard;
+        let mut summary = ShutdownSummary::default();
+        for msg_send in msg_senders {
+            if !discard {
+                summary.pending_messages += msg_send.pending();
+            }
+            summary.discarded_messages.extend(msg_send.close(discard));
+            summary.closed_channels += 1;
+        }
+
+        summary
+    }
+
+    // Sends the given message to all active channels of the topic which aren't members
+    // of queue groups and to one active channel of each queue group, then invokes
+    // the callbacks according to the delivery mode.
+    //
+    // The sequence number of the message is taken from `next_seq` while the topic is locked,
+    // so messages are put into channels in the order of their sequence numbers.
+    //
+    // Channels which reject the message don't prevent the other ones from receiving it,
+    // their errors are reported in the returned [`Delivery`].
+    pub(crate) fn send_message(
+        &self,
+        msg: Arc<dyn Message>,
+        next_seq: &dyn Fn() -> u64,
+    ) -> Result<Delivery, MessageTopicError> {
+        self.send_messages(vec![msg], &mut || next_seq()).remove(0)
+    }
+
+    // Sends the given messages like [`MessageTopic::send_message`], but locks the topic
+    // only once, so the messages are put into each channel contiguously.
+    //
+    // `next_seq` is called for each message in the given order while the topic is locked.
+    //
+    // Returns the result of sending each message.
+    pub(crate) fn send_messages(
+        &self,
+        msgs: Vec<Arc<dyn Message>>,
+        next_seq: &mut dyn FnMut() -> u64,
+    ) -> Vec<Result<Delivery, MessageTopicError>> {
+        let mut locked_topic = self.lock();
+        let results = msgs
+            .iter()
+            .map(|msg| locked_topic.send_message(msg, next_seq()))
+            .collect();
+        locked_topic.unlock();
+
+        results
+    }
+
+    // Locks the topic until the returned [`LockedMessageTopic`] is unlocked,
+    // so messages of several topics can be sent while all of them are locked.
+    pub(crate) fn lock(&self) -> LockedMessageTopic<'_> {
+        LockedMessageTopic {
+            msg_topic: self,
+            state: util::lock(&self.state),
+            direct_msgs: Vec::new(),
+        }
+    }
+
+    // Sends the message to the channels of the topic.
+    fn send_message_locked(
+        &self,
+        state: &mut MessageTopicState,
+        msg: &Arc<dyn Message>,
+        seq: u64,
+    ) -> Result<Delivery, MessageTopicError> {
+        if msg.type_id() != self.msg_type_id {
+            return Err(MessageTopicError::WrongMessageType {
+                msg_type_name: msg.type_name(),
+            });
+        }
+
+        if state.is_closed {
+            return Err(MessageTopicError::Closed {
+                msg_type_name: msg.type_name(),
+            });
+        }
+
+        if state.msg_senders.is_empty() && state.queue_groups.is_empty() {
+            return Err(MessageTopicError::NoSubscribers {
+                msg_type_name: msg.type_name(),
+            });
+        }
+
+        let now = self.clock.now();
+        let expiry = msg.ttl().or(state.ttl).and_then(|ttl| {
+            Some(Expiry {
+                // A time to live which overflows [`Instant`] never runs out.
+                deadline: now.checked_add(ttl)?,
+                policy: state.expiry_policy,
+            })
+        });
+
+        let msg_id = msg.message_id();
+        if let (Some(deduplicator), Some(id)) = (state.deduplicator.as_mut(), msg_id) {
+            if deduplicator.contains(id, now) {
+                state.dedup_stats.duplicates += 1;
+                return Ok(Delivery {
+                    is_duplicate: true,
+                    ..Delivery::default()
+                });
+            }
+        }
+
+        let is_direct = state.delivery_mode == DeliveryMode::Direct;
+        let broadcast_results = state
             .msg_senders
-            .lock()
-            .expect("The message topic is poisoned");
-        msg_senders
-            .values()
+            .iter()
+            .filter(|(channel_id, _)| !is_direct || !state.callbacks.contains_key(channel_id))
+            .map(|(_, msg_send)| msg_send)
             .filter(|msg_send| msg_send.is_active())
-            .try_for_each(|msg_send| msg_send.send(Arc::clone(&msg)))
-            .map_err(|msg_channel_err| MessageTopicError::MessageChannelError(msg_channel_err))
+            .map(|msg_send| msg_send.send(Arc::clone(msg), seq, expiry));
+        let queue_group_results = state
+            .queue_groups
+            .values_mut()
+            .filter_map(|queue_group| queue_group.send(Arc::clone(msg), seq, expiry));
+
+        // Callbacks which are invoked directly receive the message after the topic is unlocked.
+        let mut delivery = Delivery {
+            received: if is_direct { state.callbacks.len() } else { 0 },
+            ..Delivery::default()
+        };
+        for res in broadcast_results.chain(queue_group_results) {
+            match res {
+                Ok(()) => delivery.received += 1,
+                Err(msg_channel_err) => delivery.rejections.push(msg_channel_err),
+            }
+        }
+
+        // Messages which weren't delivered to anybody aren't remembered,
+        // so they can be published again, e.g. from the dead-letter queue.
+        if delivery.received > 0 {
+            if let (Some(deduplicator), Some(id)) = (state.deduplicator.as_mut(), msg_id) {
+                deduplicator.insert(id, now);
+                state.dedup_stats.unique += 1;
+            }
+        }
+
+        Ok(delivery)
+    }
+}
+
+// A message topic which stays locked while messages are sent to it.
+//
+// The callbacks of the topic are invoked once it is unlocked.
+pub(crate) struct LockedMessageTopic<'a> {
+    msg_topic: &'a MessageTopic,
+    state: MutexGuard<'a, MessageTopicState>,
+    // The messages which are passed to the callbacks in Direct delivery mode.
+    direct_msgs: Vec<Arc<dyn Message>>,
+}
+
+impl LockedMessageTopic<'_> {
+    // Sends the message with the given sequence number like [`MessageTopic::send_message`].
+    pub(crate) fn send_message(
+        &mut self,
+        msg: &Arc<dyn Message>,
+        seq: u64,
+    ) -> Result<Delivery, MessageTopicError> {
+        let res = self
+            .msg_topic
+            .send_message_locked(&mut self.state, msg, seq);
+
+        // Only messages which were accepted by the topic reach the callbacks.
+        if self.state.delivery_mode == DeliveryMode::Direct
+            && res.as_ref().is_ok_and(|delivery| !delivery.is_duplicate)
+        {
+            self.direct_msgs.push(Arc::clone(msg));
+        }
+
+        res
+    }
+
+    // Unlocks the topic and invokes the callbacks according to the delivery mode.
+    //
+    // Callbacks are invoked without the lock, so they can publish messages
+    // and register or unregister other callbacks.
+    pub(crate) fn unlock(self) {
+        let LockedMessageTopic {
+            state, direct_msgs, ..
+        } = self;
+        let is_direct = state.delivery_mode == DeliveryMode::Direct;
+        let callbacks: Vec<_> = state.callbacks.values().map(Arc::clone).collect();
+        drop(state);
+
+        if callbacks.is_empty() {
+            return;
+        }
+        if is_direct {
+            direct_msgs
+                .into_iter()
+                .for_each(|msg| callback::deliver_direct(callbacks.clone(), msg));
+        } else {
+            callbacks.iter().for_each(|callback| callback.run());
+        }
     }
 }
 
+#[derive(Debug)]
 pub enum MessageTopicError {
     MessageChannelError(MessageChannelError),
-    WrongMessageType,
+    /// The topic doesn't accept messages of the given type.
+    WrongMessageType {
+        msg_type_name: &'static str,
+    },
     ChannelNotFound,
+    /// Nobody listens for messages of the given type.
+    NoSubscribers {
+        msg_type_name: &'static str,
+    },
+    /// The topic was closed when the message broker was shut down,
+    /// so the message of the given type was rejected.
+    Closed {
+        msg_type_name: &'static str,
+    },
+}
+
+impl fmt::Display for MessageTopicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageTopicError::MessageChannelError(_) => {
+                write!(f, "failed to send the message to a message channel")
+            }
+            MessageTopicError::WrongMessageType { msg_type_name } => write!(
+                f,
+                "the message topic doesn't accept messages of type `{}`",
+                msg_type_name
+            ),
+            MessageTopicError::ChannelNotFound => {
+                write!(f, "the message channel isn't found in the message topic")
+            }
+            MessageTopicError::NoSubscribers { msg_type_name } => {
+                write!(f, "nobody listens for messages of type `{}`", msg_type_name)
+            }
+            MessageTopicError::Closed { msg_type_name } => write!(
+                f,
+                "the message topic is closed and rejected a message of type `{}`",
+                msg_type_name
+            ),
+        }
+    }
+}
+
+impl Error for MessageTopicError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MessageTopicError::MessageChannelError(msg_channel_err) => Some(msg_channel_err),
+            _ => None,
+        }
+    }
+}
+
+impl From<MessageChannelError> for MessageTopicError {
+    fn from(msg_channel_err: MessageChannelError) -> Self {
+        MessageTopicError::MessageChannelError(msg_channel_err)
+    }
 }
@@ -1,6 +1,9 @@
-use std::any::Any;
-use std::rc::Rc;
-use std::sync::Arc;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use core::any::Any;
+#[cfg(feature = "std")]
+use std::sync::{Mutex, MutexGuard, PoisonError};
 
 /// Downcasts [`Arc<dyn Message>`] to the one of the given types and runs the code
 /// which corresponds to it.
@@ -10,25 +13,24 @@ use std::sync::Arc;
 macro_rules! match_message {
     ($msg:ident { $( $msg_tt:tt )* }) => {
         {
-            match_message!(@arm $msg as $( $msg_tt )*);
+            $crate::match_message!(@arm $msg as $( $msg_tt )*);
         }
     };
     (@arm $msg:ident as $msg_ty:ty => $msg_handler:block $(,)?) => {
-        use $crate::AsAny;
-        use ::std::any::Any;
-        if let Ok($msg) = ::std::sync::Arc::clone(&$msg).as_any_arc().downcast::<$msg_ty>() {
+        use $crate::__private::AsAny as _;
+        if let Ok($msg) = ::core::clone::Clone::clone(&$msg).as_any_arc().downcast::<$msg_ty>() {
             $msg_handler;
         }
     };
     (@arm $msg:ident as $msg_ty:ty => $msg_handler:expr $(,)?) => {
-        match_message!(@arm $msg as $msg_ty => { $msg_handler; });
+        $crate::match_message!(@arm $msg as $msg_ty => { $msg_handler; });
     };
     (@arm $msg:ident as $msg_ty:ty => $msg_handler:block, $( $msg_tt:tt )*) => {
-        match_message!(@arm $msg as $msg_ty => $msg_handler);
-        match_message!(@arm $msg as $( $msg_tt )*);
+        $crate::match_message!(@arm $msg as $msg_ty => $msg_handler);
+        $crate::match_message!(@arm $msg as $( $msg_tt )*);
     };
     (@arm $msg:ident as $msg_ty:ty => $msg_handler:expr, $( $msg_tt:tt )*) => {
-        match_message!(@arm $msg as $msg_ty => { $msg_handler; }, $( $msg_tt )*);
+        $crate::match_message!(@arm $msg as $msg_ty => { $msg_handler; }, $( $msg_tt )*);
     };
 }
 
@@ -72,3 +74,18 @@ impl<T: 'static> AsAny for T {
         self
     }
 }
+
+// Locks the given mutex, recovering it if it was poisoned by a panicking thread,
+// so a panic on one thread doesn't make the broker unusable on the other ones.
+//
+// Recovering is sound for the mutexes of this crate:
+// - topics, channels, subscriptions, dispatchers and schedulers don't call user code
+//   while they hold their locks, except the methods of [`Message`], which are called
+//   before the guarded state is changed for that message;
+// - the mutex of a callback guards only its handler, which is left the way it panicked,
+//   just like with [`PanicPolicy::Catch`];
+// - the `running` mutex of a scheduler guards no data.
+#[cfg(feature = "std")]
+pub(crate) fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -1,6 +1,8 @@
+#![cfg(feature = "std")]
+
 use lps::*;
 
-use std::{cell::RefCell, sync::Arc};
+use std::{cell::RefCell, rc::Rc, sync::Arc, time::Duration};
 
 #[derive(Debug, Clone)]
 struct TestMsg0 {
@@ -115,6 +117,28 @@ impl Subscriber for TestSubsciber0 {
 
         self.data.append(&mut *tmp_data.borrow_mut());
     }
+
+    fn process_messages_max(&mut self, max: usize) -> ProcessedMessages {
+        let tmp_data = RefCell::new(vec![]);
+
+        let processed = self.sub.process_messages_max(
+            max,
+            Box::new(
+                (|msg: Arc<dyn Message>| {
+                    if let Some(msg) = (*msg).as_any_ref().downcast_ref::<TestMsg0>() {
+                        tmp_data.borrow_mut().push((msg.pub_id, 0, msg.msg_id));
+                    } else if let Some(msg) = (*msg).as_any_ref().downcast_ref::<TestMsg2>() {
+                        tmp_data.borrow_mut().push((msg.pub_id, 2, msg.msg_id));
+                    }
+                })
+                .into_message_handler(),
+            ),
+        );
+
+        self.data.append(&mut *tmp_data.borrow_mut());
+
+        processed
+    }
 }
 
 struct TestSubsciber1 {
@@ -165,6 +189,32 @@ impl Subscriber for TestSubsciber1 {
             self.data.push((msg.pub_id, 1, msg.msg_id));
         });
     }
+
+    fn process_messages_max(&mut self, max: usize) -> ProcessedMessages {
+        let processed = self.sub0.process_messages_max(max, |msg| {
+            self.data.push((msg.pub_id, 0, msg.msg_id));
+        });
+
+        processed
+            + self
+                .sub1
+                .process_messages_max(max - processed.handled, |msg| {
+                    self.data.push((msg.pub_id, 1, msg.msg_id));
+                })
+    }
+}
+
+// Receives all pending messages and returns the type and the id of each one.
+fn recv_msg_ids(multi_sub: &MultiSubscription) -> Vec<(u32, u32)> {
+    let data = RefCell::new(vec![]);
+    multi_sub
+        .message_iter()
+        .handle(|msg: Arc<TestMsg0>| data.borrow_mut().push((0, msg.msg_id)))
+        .handle(|msg: Arc<TestMsg1>| data.borrow_mut().push((1, msg.msg_id)))
+        .handle(|msg: Arc<TestMsg2>| data.borrow_mut().push((2, msg.msg_id)))
+        .run();
+
+    data.into_inner()
 }
 
 #[test]
@@ -636,3 +686,1922 @@ fn test_multi_broker_multi_pub_multi_sub() {
         }
     }
 }
+
+#[test]
+fn test_dead_letters() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let dead_letters: Subscription<DeadLetter> = Subscription::new(Arc::clone(&broker));
+    let mut sub0: Subscription<TestMsg0> = Subscription::unregistered();
+
+    {
+        pub0.publish(Arc::new(TestMsg0::new(0, 0)));
+
+        let dead_letter = dead_letters.recv_message().unwrap();
+        assert_eq!(DeadLetterReason::NoSubscribers, dead_letter.reason());
+        assert!(dead_letters.recv_message().is_none());
+
+        let _ = sub0.register(Arc::clone(&broker));
+        assert!(dead_letter.republish(&*broker).is_ok());
+    }
+
+    {
+        let mut data = vec![];
+        ErasedSubscription::process_messages(
+            &sub0,
+            Box::new(
+                (|msg: Arc<TestMsg2>| data.push((msg.pub_id, 2, msg.msg_id)))
+                    .into_message_handler(),
+            ),
+        );
+        assert!(data.is_empty());
+        assert_eq!(0, sub0.pending_messages());
+        assert!(dead_letters.recv_message().is_none());
+    }
+
+    {
+        let mut multi_sub = MultiSubscription::unregistered();
+        multi_sub.add::<TestMsg0>().add::<TestMsg1>();
+        let _ = multi_sub.register(Arc::clone(&broker));
+
+        pub0.publish(Arc::new(TestMsg0::new(0, 1)));
+        pub0.publish(Arc::new(TestMsg1::new(0, 2)));
+
+        let mut data = vec![];
+        ErasedSubscription::process_messages(
+            &multi_sub,
+            Box::new(
+                (|msg: Arc<TestMsg1>| data.push((msg.pub_id, 1, msg.msg_id)))
+                    .into_message_handler(),
+            ),
+        );
+        assert_eq!(vec![(0, 1, 2)], data);
+        assert!(dead_letters.recv_message().is_none());
+    }
+}
+
+#[test]
+fn test_handler_panic_is_caught() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let dead_letters: Subscription<DeadLetter> = Subscription::new(Arc::clone(&broker));
+    let mut sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+    sub0.set_panic_policy(PanicPolicy::Catch);
+
+    pub0.publish(Arc::new(TestMsg0::new(0, 0)));
+    pub0.publish(Arc::new(TestMsg0::new(0, 1)));
+
+    let mut data = vec![];
+    sub0.process_messages(|msg| {
+        if msg.msg_id == 0 {
+            panic!("failed to handle the message");
+        }
+
+        data.push((msg.pub_id, 0, msg.msg_id));
+    });
+    assert_eq!(vec![(0, 0, 1)], data);
+
+    let dead_letter = dead_letters.recv_message().unwrap();
+    assert_eq!(DeadLetterReason::HandlerFailed, dead_letter.reason());
+    match dead_letter.handler_error() {
+        Some(MessageHandlerError::Panicked {
+            msg_type_name,
+            panic_message,
+        }) => {
+            assert_eq!(std::any::type_name::<TestMsg0>(), *msg_type_name);
+            assert_eq!(
+                Some("failed to handle the message"),
+                panic_message.as_deref()
+            );
+        }
+        _ => panic!("the handler error is not reported"),
+    }
+
+    pub0.publish(Arc::new(TestMsg0::new(0, 2)));
+    pub0.publish(Arc::new(TestMsg0::new(0, 3)));
+
+    let mut data = vec![];
+    sub0.message_iter()
+        .limit(2)
+        .handle(|msg: Arc<TestMsg0>| {
+            if msg.msg_id == 2 {
+                panic!("failed to handle the message");
+            }
+
+            data.push((msg.pub_id, 0, msg.msg_id));
+        })
+        .run();
+    assert_eq!(vec![(0, 0, 3)], data);
+
+    let mut sub1: Subscription<TestMsg1> = Subscription::new(Arc::clone(&broker));
+    sub1.set_panic_policy(PanicPolicy::Catch);
+    pub0.publish(Arc::new(TestMsg0::new(0, 4)));
+    pub0.publish(Arc::new(TestMsg1::new(0, 5)));
+
+    let mut data = vec![];
+    sub0.message_iter()
+        .chain_messages(sub1.message_iter())
+        .handle(|msg: Arc<TestMsg0>| {
+            if msg.msg_id == 4 {
+                panic!("failed to handle the message");
+            }
+        })
+        .handle(|msg: Arc<TestMsg1>| data.push((msg.pub_id, 1, msg.msg_id)))
+        .run();
+    assert_eq!(vec![(0, 1, 5)], data);
+}
+
+#[test]
+fn test_message_iterator_combinators() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+    let sub1: Subscription<TestMsg1> = Subscription::new(Arc::clone(&broker));
+
+    for i in 0..3 {
+        pub0.publish(Arc::new(TestMsg0::new(0, i)));
+        pub0.publish(Arc::new(TestMsg1::new(0, i)));
+    }
+
+    {
+        let msg_ids: Vec<_> = sub0
+            .message_iter()
+            .limit(2)
+            .filter_type::<TestMsg0>()
+            .map(|msg| msg.msg_id)
+            .collect();
+        assert_eq!(vec![0, 1], msg_ids);
+        assert_eq!(1, sub0.pending_messages());
+    }
+
+    {
+        let mut inspected = vec![];
+        let (msgs0, msgs1) = sub0
+            .message_iter()
+            .chain(sub1.message_iter())
+            .inspect_type(|msg: &TestMsg1| inspected.push(msg.msg_id))
+            .partition_by_type::<TestMsg0>();
+        assert_eq!(
+            vec![2],
+            msgs0.iter().map(|msg| msg.msg_id).collect::<Vec<_>>()
+        );
+        assert_eq!(3, msgs1.len());
+        assert_eq!(vec![0, 1, 2], inspected);
+    }
+
+    {
+        pub0.publish(Arc::new(TestMsg0::new(0, 3)));
+
+        let mut data = vec![];
+        sub0.message_iter()
+            .take_while_pending()
+            .handle(|msg: Arc<TestMsg0>| {
+                data.push((msg.pub_id, 0, msg.msg_id));
+                pub0.publish(Arc::new(TestMsg0::new(0, msg.msg_id + 1)));
+            })
+            .run();
+        assert_eq!(vec![(0, 0, 3)], data);
+        assert_eq!(1, sub0.pending_messages());
+    }
+}
+
+#[test]
+fn test_budgeted_processing() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let mut sub0 = TestSubsciber0::new();
+    sub0.subscribe(Arc::clone(&broker));
+    let sub1: Subscription<TestMsg1> = Subscription::new(Arc::clone(&broker));
+
+    for i in 0..3 {
+        pub0.publish(Arc::new(TestMsg0::new(0, i)));
+        pub0.publish(Arc::new(TestMsg1::new(0, i)));
+        pub0.publish(Arc::new(TestMsg2::new(0, i)));
+    }
+
+    {
+        let processed = sub0.process_messages_max(4);
+        assert_eq!(4, processed.handled);
+        assert!(processed.has_more);
+
+        let processed = sub0.process_messages_max(4);
+        assert_eq!(2, processed.handled);
+        assert!(!processed.has_more);
+
+        assert_eq!(6, sub0.data.len());
+    }
+
+    {
+        for i in 3..6 {
+            pub0.publish(Arc::new(TestMsg0::new(0, i)));
+        }
+
+        let processed = sub0.process_messages_for(Duration::ZERO);
+        assert_eq!(0, processed.handled);
+        assert!(processed.has_more);
+
+        let processed = sub0.process_messages_for(Duration::from_secs(60));
+        assert_eq!(3, processed.handled);
+        assert!(!processed.has_more);
+
+        assert_eq!(9, sub0.data.len());
+    }
+
+    {
+        let mut data = vec![];
+        let processed = sub1.process_messages_for(Duration::ZERO, |msg| data.push(msg.msg_id));
+        assert_eq!(0, processed.handled);
+        assert!(processed.has_more);
+
+        let processed = sub1.process_messages_for(Duration::from_secs(60), |msg| {
+            data.push(msg.msg_id);
+        });
+        assert_eq!(3, processed.handled);
+        assert!(!processed.has_more);
+        assert_eq!(vec![0, 1, 2], data);
+    }
+}
+
+#[test]
+fn test_multi_subscription_receive_strategies() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub
+        .add::<TestMsg0>()
+        .add::<TestMsg1>()
+        .add::<TestMsg2>();
+    let _ = multi_sub.register(Arc::clone(&broker));
+
+    let publish = || {
+        for i in 0..2 {
+            pub0.publish(Arc::new(TestMsg0::new(0, i)));
+            pub0.publish(Arc::new(TestMsg0::new(0, i + 2)));
+            pub0.publish(Arc::new(TestMsg2::new(0, i)));
+            pub0.publish(Arc::new(TestMsg1::new(0, i)));
+        }
+    };
+
+    publish();
+    assert_eq!(
+        vec![
+            (0, 0),
+            (0, 2),
+            (0, 1),
+            (0, 3),
+            (1, 0),
+            (1, 1),
+            (2, 0),
+            (2, 1)
+        ],
+        recv_msg_ids(&multi_sub)
+    );
+
+    publish();
+    multi_sub.set_receive_strategy(ReceiveStrategy::RoundRobin);
+    assert_eq!(
+        vec![
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (0, 2),
+            (1, 1),
+            (2, 1),
+            (0, 1),
+            (0, 3)
+        ],
+        recv_msg_ids(&multi_sub)
+    );
+
+    publish();
+    multi_sub
+        .set_receive_strategy(ReceiveStrategy::Weighted)
+        .set_weight::<TestMsg0>(3);
+    assert_eq!(
+        vec![
+            (0, 0),
+            (0, 2),
+            (0, 1),
+            (1, 0),
+            (2, 0),
+            (0, 3),
+            (1, 1),
+            (2, 1)
+        ],
+        recv_msg_ids(&multi_sub)
+    );
+
+    publish();
+    multi_sub.set_receive_strategy(ReceiveStrategy::PublishOrder);
+    assert_eq!(
+        vec![
+            (0, 0),
+            (0, 2),
+            (2, 0),
+            (1, 0),
+            (0, 1),
+            (0, 3),
+            (2, 1),
+            (1, 1)
+        ],
+        recv_msg_ids(&multi_sub)
+    );
+
+    // Every message broker numbers its messages on its own.
+    let other_broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+    let other_sub: Subscription<TestMsg0> = Subscription::new(Arc::clone(&other_broker));
+    let _ = other_broker.publish_value(TestMsg0::new(0, 0));
+    assert_eq!(Some(0), other_sub.peek_sequence_number());
+}
+
+#[test]
+fn test_multi_subscription_per_type_control() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let dead_letters: Subscription<DeadLetter> = Subscription::new(Arc::clone(&broker));
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub
+        .add::<TestMsg0>()
+        .add::<TestMsg1>()
+        .add::<TestMsg2>();
+    let _ = multi_sub.register(Arc::clone(&broker));
+
+    assert!(multi_sub.contains::<TestMsg1>());
+    assert!(matches!(
+        multi_sub.try_add::<TestMsg1>(),
+        Err(SubscriptionError::MessageTypeAlreadyAdded { .. })
+    ));
+    assert_eq!(
+        vec![
+            MessageTypeId::of::<TestMsg0>(),
+            MessageTypeId::of::<TestMsg1>(),
+            MessageTypeId::of::<TestMsg2>(),
+        ],
+        multi_sub.message_type_ids().collect::<Vec<_>>()
+    );
+
+    {
+        assert!(multi_sub.deactivate_type::<TestMsg0>().is_ok());
+        assert!(!multi_sub.is_active_type::<TestMsg0>());
+        assert!(multi_sub.is_active_type::<TestMsg1>());
+
+        pub0.publish(Arc::new(TestMsg0::new(0, 0)));
+        pub0.publish(Arc::new(TestMsg1::new(0, 1)));
+
+        let (msgs0, msgs1) = multi_sub.message_iter().partition_by_type::<TestMsg0>();
+        assert!(msgs0.is_empty());
+        assert_eq!(1, msgs1.len());
+
+        assert!(multi_sub.activate_type::<TestMsg0>().is_ok());
+        assert!(multi_sub.is_active_type::<TestMsg0>());
+    }
+
+    {
+        assert!(multi_sub.remove::<TestMsg1>().is_ok());
+        assert!(!multi_sub.contains::<TestMsg1>());
+        assert!(matches!(
+            multi_sub.remove::<TestMsg1>(),
+            Err(SubscriptionError::MessageTypeNotFound { .. })
+        ));
+
+        pub0.publish(Arc::new(TestMsg1::new(0, 2)));
+        assert!(multi_sub.recv_message().is_none());
+
+        let dead_letter = dead_letters.recv_message().unwrap();
+        assert_eq!(DeadLetterReason::NoSubscribers, dead_letter.reason());
+    }
+}
+
+#[test]
+fn test_multi_subscription_register_stores_message_broker() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>();
+    assert!(!multi_sub.is_registered());
+    assert!(multi_sub.message_broker().is_none());
+
+    assert!(multi_sub.register(Arc::clone(&broker)).is_ok());
+    assert!(multi_sub.is_registered());
+    assert!(Arc::ptr_eq(&broker, &multi_sub.message_broker().unwrap()));
+
+    let _ = broker.publish_message(Arc::new(TestMsg0::new(0, 0)));
+    assert_eq!(vec![(0, 0)], recv_msg_ids(&multi_sub));
+}
+
+#[test]
+fn test_multi_subscription_register_twice_keeps_first_broker() {
+    let broker0: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+    let broker1: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>().add::<TestMsg1>();
+
+    assert!(multi_sub.register(Arc::clone(&broker0)).is_ok());
+    assert!(matches!(
+        multi_sub.register(Arc::clone(&broker1)),
+        Err(SubscriptionError::AlreadyRegistered)
+    ));
+    assert!(Arc::ptr_eq(&broker0, &multi_sub.message_broker().unwrap()));
+
+    let _ = broker0.publish_message(Arc::new(TestMsg0::new(0, 0)));
+    let _ = broker1.publish_message(Arc::new(TestMsg1::new(0, 1)));
+    assert_eq!(vec![(0, 0)], recv_msg_ids(&multi_sub));
+}
+
+#[test]
+fn test_multi_subscription_add_after_register_registers_new_subscription() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>();
+    let _ = multi_sub.register(Arc::clone(&broker));
+
+    multi_sub.add::<TestMsg1>();
+
+    let _ = broker.publish_message(Arc::new(TestMsg0::new(0, 0)));
+    let _ = broker.publish_message(Arc::new(TestMsg1::new(0, 1)));
+    assert_eq!(vec![(0, 0), (1, 1)], recv_msg_ids(&multi_sub));
+}
+
+#[test]
+fn test_multi_subscription_add_after_deactivate_adds_inactive_subscription() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>();
+    let _ = multi_sub.register(Arc::clone(&broker));
+    let _ = multi_sub.deactivate();
+
+    multi_sub.add::<TestMsg1>();
+    assert!(!multi_sub.is_active_type::<TestMsg1>());
+
+    let _ = broker.publish_message(Arc::new(TestMsg0::new(0, 0)));
+    let _ = broker.publish_message(Arc::new(TestMsg1::new(0, 1)));
+    assert!(recv_msg_ids(&multi_sub).is_empty());
+
+    let _ = multi_sub.activate();
+
+    let _ = broker.publish_message(Arc::new(TestMsg1::new(0, 2)));
+    assert_eq!(vec![(1, 2)], recv_msg_ids(&multi_sub));
+}
+
+#[test]
+fn test_multi_subscription_register_after_deactivate_stays_inactive() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>();
+    let _ = multi_sub.deactivate();
+    let _ = multi_sub.register(Arc::clone(&broker));
+    assert!(!multi_sub.is_active());
+    assert!(!multi_sub.is_active_type::<TestMsg0>());
+
+    let _ = broker.publish_message(Arc::new(TestMsg0::new(0, 0)));
+    assert!(recv_msg_ids(&multi_sub).is_empty());
+}
+
+#[test]
+fn test_multi_subscription_unregister() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>().add::<TestMsg1>();
+    assert!(matches!(
+        multi_sub.unregister(),
+        Err(SubscriptionError::NotRegistered)
+    ));
+
+    let _ = multi_sub.register(Arc::clone(&broker));
+    assert!(multi_sub.unregister().is_ok());
+    assert!(!multi_sub.is_registered());
+    assert!(multi_sub.message_broker().is_none());
+    assert!(matches!(
+        multi_sub.unregister(),
+        Err(SubscriptionError::NotRegistered)
+    ));
+
+    let _ = broker.publish_message(Arc::new(TestMsg0::new(0, 0)));
+    assert!(recv_msg_ids(&multi_sub).is_empty());
+
+    multi_sub.add::<TestMsg2>();
+    let _ = broker.publish_message(Arc::new(TestMsg2::new(0, 1)));
+    assert!(recv_msg_ids(&multi_sub).is_empty());
+}
+
+#[test]
+fn test_multi_subscription_reregister_in_another_broker() {
+    let broker0: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+    let broker1: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>().add::<TestMsg1>();
+
+    let _ = multi_sub.register(Arc::clone(&broker0));
+    let _ = multi_sub.unregister();
+    assert!(multi_sub.register(Arc::clone(&broker1)).is_ok());
+    assert!(Arc::ptr_eq(&broker1, &multi_sub.message_broker().unwrap()));
+
+    let _ = broker0.publish_message(Arc::new(TestMsg0::new(0, 0)));
+    let _ = broker1.publish_message(Arc::new(TestMsg1::new(0, 1)));
+    assert_eq!(vec![(1, 1)], recv_msg_ids(&multi_sub));
+}
+
+#[test]
+fn test_paused_subscription_buffers_messages() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let dead_letters: Subscription<DeadLetter> = Subscription::new(Arc::clone(&broker));
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+
+    {
+        assert!(sub0.pause(None).is_ok());
+        assert!(sub0.is_paused());
+        assert!(sub0.is_active());
+
+        pub0.publish(Arc::new(TestMsg0::new(0, 0)));
+        pub0.publish(Arc::new(TestMsg0::new(0, 1)));
+        assert!(sub0.recv_message().is_none());
+        assert_eq!(0, sub0.pending_messages());
+
+        assert!(sub0.resume().is_ok());
+        assert!(!sub0.is_paused());
+
+        let mut msg_ids = vec![];
+        sub0.process_messages(|msg| msg_ids.push(msg.msg_id));
+        assert_eq!(vec![0, 1], msg_ids);
+    }
+
+    {
+        assert!(sub0.pause(Some(1)).is_ok());
+
+        pub0.publish(Arc::new(TestMsg0::new(0, 2)));
+        pub0.publish(Arc::new(TestMsg0::new(0, 3)));
+
+        let dead_letter = dead_letters.recv_message().unwrap();
+        assert_eq!(DeadLetterReason::BufferFull, dead_letter.reason());
+        let msg = dead_letter.message().as_any_arc().downcast::<TestMsg0>();
+        assert_eq!(3, msg.unwrap().msg_id);
+
+        assert!(sub0.resume().is_ok());
+
+        let mut msg_ids = vec![];
+        sub0.process_messages(|msg| msg_ids.push(msg.msg_id));
+        assert_eq!(vec![2], msg_ids);
+    }
+
+    {
+        assert!(sub0.deactivate().is_ok());
+
+        pub0.publish(Arc::new(TestMsg0::new(0, 4)));
+
+        assert!(sub0.activate().is_ok());
+        assert!(sub0.recv_message().is_none());
+    }
+
+    {
+        let sub1: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+
+        // Messages which were queued before the pause don't take up the capacity.
+        pub0.publish(Arc::new(TestMsg0::new(0, 5)));
+        assert!(sub0.pause(Some(1)).is_ok());
+        pub0.publish(Arc::new(TestMsg0::new(0, 6)));
+        assert!(broker.publish_value(TestMsg0::new(0, 7)).is_ok());
+
+        let dead_letter = dead_letters.recv_message().unwrap();
+        assert_eq!(DeadLetterReason::BufferFull, dead_letter.reason());
+        assert!(dead_letters.recv_message().is_none());
+
+        // Each subscription which rejects the message produces its own dead letter.
+        assert!(sub1.pause(Some(0)).is_ok());
+        assert!(broker.publish_value(TestMsg0::new(0, 8)).is_err());
+        assert_eq!(2, dead_letters.message_iter().count());
+
+        assert!(sub0.resume().is_ok());
+        assert!(sub1.resume().is_ok());
+
+        let mut msg_ids = vec![];
+        sub0.process_messages(|msg| msg_ids.push(msg.msg_id));
+        assert_eq!(vec![5, 6], msg_ids);
+
+        let mut msg_ids = vec![];
+        sub1.process_messages(|msg| msg_ids.push(msg.msg_id));
+        assert_eq!(vec![5, 6, 7], msg_ids);
+    }
+
+    {
+        let mut multi_sub = MultiSubscription::unregistered();
+        multi_sub.add::<TestMsg1>().add::<TestMsg2>();
+        let _ = multi_sub.register(Arc::clone(&broker));
+
+        // The capacity limits messages of each type on its own.
+        assert!(multi_sub.pause(Some(1)).is_ok());
+        let _ = broker.publish_value(TestMsg1::new(0, 9));
+        let _ = broker.publish_value(TestMsg1::new(0, 10));
+        let _ = broker.publish_value(TestMsg2::new(0, 11));
+
+        let dead_letter = dead_letters.recv_message().unwrap();
+        assert_eq!(DeadLetterReason::BufferFull, dead_letter.reason());
+        let msg = dead_letter.message().as_any_arc().downcast::<TestMsg1>();
+        assert_eq!(10, msg.unwrap().msg_id);
+        assert!(dead_letters.recv_message().is_none());
+
+        assert!(multi_sub.resume().is_ok());
+        assert_eq!(vec![(1, 9), (2, 11)], recv_msg_ids(&multi_sub));
+    }
+}
+
+#[test]
+fn test_shared_subscription_distributes_messages() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let shared_sub: SharedSubscription<TestMsg0> = SharedSubscription::new(Arc::clone(&broker));
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+
+    for i in 0..100 {
+        pub0.publish(Arc::new(TestMsg0::new(0, i)));
+    }
+
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let shared_sub = shared_sub.clone();
+            std::thread::spawn(move || {
+                let mut msg_ids = vec![];
+                shared_sub.process_messages(|msg| msg_ids.push(msg.msg_id));
+
+                msg_ids
+            })
+        })
+        .collect();
+
+    let mut msg_ids: Vec<_> = workers
+        .into_iter()
+        .flat_map(|worker| worker.join().unwrap())
+        .collect();
+    msg_ids.sort();
+    assert_eq!((0..100).collect::<Vec<_>>(), msg_ids);
+
+    assert_eq!(100, sub0.pending_messages());
+}
+
+#[test]
+fn test_subscriptions_are_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Subscription<TestMsg0>>();
+    assert_send_sync::<SharedSubscription<TestMsg0>>();
+    assert_send_sync::<MultiSubscription>();
+}
+
+#[test]
+fn test_match_message() {
+    let msgs: Vec<Arc<dyn Message>> = vec![
+        Arc::new(TestMsg0::new(0, 0)),
+        Arc::new(TestMsg1::new(0, 1)),
+        Arc::new(TestMsg2::new(0, 2)),
+    ];
+
+    let mut msg_ids = vec![];
+    for msg in msgs {
+        lps::match_message!(msg {
+            TestMsg0 => msg_ids.push((0, msg.msg_id)),
+            TestMsg1 => {
+                msg_ids.push((1, msg.msg_id));
+            },
+        });
+    }
+    assert_eq!(vec![(0, 0), (1, 1)], msg_ids);
+}
+
+#[test]
+fn test_queue_groups() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let workers = QueueGroup::new("workers");
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+    let worker0: Subscription<TestMsg0> =
+        Subscription::in_queue_group(Arc::clone(&broker), workers.clone());
+    let worker1: Subscription<TestMsg0> =
+        Subscription::in_queue_group(Arc::clone(&broker), workers.clone());
+
+    let recv_msg_ids = |sub: &Subscription<TestMsg0>| {
+        let mut msg_ids = vec![];
+        sub.process_messages(|msg| msg_ids.push(msg.msg_id));
+
+        msg_ids
+    };
+
+    {
+        for i in 0..4 {
+            pub0.publish(Arc::new(TestMsg0::new(0, i)));
+        }
+
+        assert_eq!(vec![0, 1, 2, 3], recv_msg_ids(&sub0));
+        assert_eq!(vec![0, 2], recv_msg_ids(&worker0));
+        assert_eq!(vec![1, 3], recv_msg_ids(&worker1));
+    }
+
+    {
+        let _ = worker0.deactivate();
+
+        for i in 4..6 {
+            pub0.publish(Arc::new(TestMsg0::new(0, i)));
+        }
+
+        assert!(recv_msg_ids(&worker0).is_empty());
+        assert_eq!(vec![4, 5], recv_msg_ids(&worker1));
+    }
+
+    {
+        let least_loaded =
+            QueueGroup::new("least_loaded").with_strategy(QueueGroupStrategy::LeastLoaded);
+        let worker2: Subscription<TestMsg0> =
+            Subscription::in_queue_group(Arc::clone(&broker), least_loaded.clone());
+        let worker3: Subscription<TestMsg0> =
+            Subscription::in_queue_group(Arc::clone(&broker), least_loaded);
+
+        for i in 6..8 {
+            pub0.publish(Arc::new(TestMsg0::new(0, i)));
+        }
+        assert_eq!(vec![7], recv_msg_ids(&worker3));
+
+        for i in 8..10 {
+            pub0.publish(Arc::new(TestMsg0::new(0, i)));
+        }
+        assert_eq!(vec![6, 9], recv_msg_ids(&worker2));
+        assert_eq!(vec![8], recv_msg_ids(&worker3));
+
+        // A paused member is tried last, its empty buffer doesn't make it the least loaded one.
+        let _ = worker2.pause(Some(1));
+        for i in 10..12 {
+            pub0.publish(Arc::new(TestMsg0::new(0, i)));
+        }
+        let _ = worker2.resume();
+        assert!(recv_msg_ids(&worker2).is_empty());
+        assert_eq!(vec![10, 11], recv_msg_ids(&worker3));
+
+        // Ties are broken by the turn.
+        let (mut msg_ids2, mut msg_ids3) = (vec![], vec![]);
+        for i in 12..16 {
+            pub0.publish(Arc::new(TestMsg0::new(0, i)));
+            msg_ids2.extend(recv_msg_ids(&worker2));
+            msg_ids3.extend(recv_msg_ids(&worker3));
+        }
+        assert_eq!(vec![12, 14], msg_ids2);
+        assert_eq!(vec![13, 15], msg_ids3);
+    }
+
+    {
+        let fallback = QueueGroup::new("fallback");
+        let worker4: Subscription<TestMsg0> =
+            Subscription::in_queue_group(Arc::clone(&broker), fallback.clone());
+        let worker5: Subscription<TestMsg0> =
+            Subscription::in_queue_group(Arc::clone(&broker), fallback);
+
+        // A member which rejects the message hands it over to the next one.
+        let _ = worker4.pause(Some(0));
+        for i in 16..18 {
+            assert!(broker.publish_value(TestMsg0::new(0, i)).is_ok());
+        }
+        let _ = worker4.resume();
+        assert!(recv_msg_ids(&worker4).is_empty());
+        assert_eq!(vec![16, 17], recv_msg_ids(&worker5));
+    }
+}
+
+struct TestDispatchedSubscriber {
+    sub: Subscription<TestMsg1>,
+
+    data: Arc<std::sync::Mutex<Vec<u32>>>,
+}
+
+impl Subscriber for TestDispatchedSubscriber {
+    fn subscribe(&mut self, msg_broker: Arc<dyn MessageBroker>) {
+        let _ = self.sub.register(msg_broker);
+    }
+
+    fn unsubscribe(&mut self) {
+        let _ = self.sub.unregister();
+    }
+
+    fn activate(&self) {
+        let _ = self.sub.activate();
+    }
+
+    fn deactivate(&self) {
+        let _ = self.sub.deactivate();
+    }
+
+    fn set_waker(&self, waker: Option<std::task::Waker>) {
+        self.sub.set_waker(waker);
+    }
+
+    fn process_messages(&mut self) {
+        let mut data = self.data.lock().unwrap();
+        self.sub.process_messages(|msg| data.push(msg.msg_id));
+    }
+
+    fn process_messages_max(&mut self, max: usize) -> ProcessedMessages {
+        let mut data = self.data.lock().unwrap();
+        self.sub
+            .process_messages_max(max, |msg| data.push(msg.msg_id))
+    }
+}
+
+#[test]
+fn test_dispatcher() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let dispatcher = Dispatcher::builder()
+        .threads(2)
+        .poll_interval(Duration::from_secs(60))
+        .build();
+
+    let data0 = Arc::new(std::sync::Mutex::new(vec![]));
+    let id0 = dispatcher.spawn(Box::new(TestDispatchedSubscriber {
+        sub: Subscription::new(Arc::clone(&broker)),
+        data: Arc::clone(&data0),
+    }));
+    let data1 = Arc::new(std::sync::Mutex::new(vec![]));
+    dispatcher.spawn(Box::new(TestDispatchedSubscriber {
+        sub: Subscription::new(Arc::clone(&broker)),
+        data: Arc::clone(&data1),
+    }));
+    assert_eq!(2, dispatcher.len());
+
+    {
+        for i in 0..100 {
+            pub0.publish(Arc::new(TestMsg1::new(0, i)));
+        }
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while data0.lock().unwrap().len() < 100 && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!((0..100).collect::<Vec<_>>(), *data0.lock().unwrap());
+    }
+
+    {
+        let mut sub0 = dispatcher.remove(id0).unwrap();
+        assert!(dispatcher.remove(id0).is_none());
+        assert_eq!(1, dispatcher.len());
+
+        pub0.publish(Arc::new(TestMsg1::new(0, 100)));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(100, data0.lock().unwrap().len());
+
+        sub0.process_messages();
+        assert_eq!(101, data0.lock().unwrap().len());
+    }
+
+    {
+        for i in 101..200 {
+            pub0.publish(Arc::new(TestMsg1::new(0, i)));
+        }
+
+        let subscribers = dispatcher.shutdown();
+        assert_eq!(1, subscribers.len());
+        assert_eq!((0..200).collect::<Vec<_>>(), *data1.lock().unwrap());
+    }
+}
+
+#[test]
+fn test_callbacks() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    {
+        let data = Arc::new(std::sync::Mutex::new(vec![]));
+        let guard = {
+            let data = Arc::clone(&data);
+            let msg_broker = Arc::clone(&broker);
+            broker.on(move |msg: Arc<TestMsg0>| {
+                data.lock().unwrap().push(msg.msg_id);
+                if msg.msg_id < 3 {
+                    let _ = msg_broker.publish_message(Arc::new(TestMsg0::new(0, msg.msg_id + 10)));
+                }
+            })
+        };
+
+        pub0.publish(Arc::new(TestMsg0::new(0, 0)));
+        pub0.publish(Arc::new(TestMsg0::new(0, 1)));
+        assert_eq!(vec![0, 10, 1, 11], *data.lock().unwrap());
+
+        drop(guard);
+        pub0.publish(Arc::new(TestMsg0::new(0, 2)));
+        assert_eq!(4, data.lock().unwrap().len());
+    }
+
+    {
+        let dispatcher = Dispatcher::builder().threads(1).build();
+
+        let data = Arc::new(std::sync::Mutex::new(vec![]));
+        let guard = {
+            let data = Arc::clone(&data);
+            broker.on_with(
+                CallbackDelivery::Dispatcher(&dispatcher),
+                move |msg: Arc<TestMsg1>| {
+                    data.lock().unwrap().push(msg.msg_id);
+                },
+            )
+        };
+
+        for i in 0..10 {
+            pub0.publish(Arc::new(TestMsg1::new(0, i)));
+        }
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while data.lock().unwrap().len() < 10 && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!((0..10).collect::<Vec<_>>(), *data.lock().unwrap());
+
+        drop(guard);
+        assert!(dispatcher.is_empty());
+        pub0.publish(Arc::new(TestMsg1::new(0, 10)));
+        assert!(dispatcher.shutdown().is_empty());
+        assert_eq!(10, data.lock().unwrap().len());
+    }
+}
+
+#[test]
+fn test_callback_panic_is_caught() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+
+    let dead_letters: Subscription<DeadLetter> = Subscription::new(Arc::clone(&broker));
+    let data = Arc::new(std::sync::Mutex::new(vec![]));
+    let _guard = {
+        let data = Arc::clone(&data);
+        broker.on_with_policy(
+            CallbackDelivery::Sync,
+            PanicPolicy::Catch,
+            move |msg: Arc<TestMsg0>| {
+                if msg.msg_id == 0 {
+                    panic!("failed to handle the message");
+                }
+
+                data.lock().unwrap().push(msg.msg_id);
+            },
+        )
+    };
+
+    pub0.publish(Arc::new(TestMsg0::new(0, 0)));
+    pub0.publish(Arc::new(TestMsg0::new(0, 1)));
+    assert_eq!(vec![1], *data.lock().unwrap());
+
+    let dead_letter = dead_letters.recv_message().unwrap();
+    assert_eq!(DeadLetterReason::HandlerFailed, dead_letter.reason());
+    assert!(matches!(
+        dead_letter.handler_error(),
+        Some(MessageHandlerError::Panicked { .. })
+    ));
+    assert!(dead_letters.recv_message().is_none());
+}
+
+#[test]
+fn test_direct_delivery_mode() {
+    let run = |delivery_mode: DeliveryMode| {
+        let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+        broker.set_delivery_mode::<TestMsg0>(delivery_mode);
+        broker.set_delivery_mode::<TestMsg1>(delivery_mode);
+
+        let log = Arc::new(std::sync::Mutex::new(vec![]));
+        let _guard0 = {
+            let log = Arc::clone(&log);
+            let msg_broker = Arc::clone(&broker);
+            broker.on(move |msg: Arc<TestMsg0>| {
+                log.lock().unwrap().push((0, msg.msg_id));
+                if msg.msg_id < 10 {
+                    let _ = msg_broker.publish_message(Arc::new(TestMsg0::new(0, msg.msg_id + 10)));
+                }
+                let _ = msg_broker.publish_message(Arc::new(TestMsg1::new(0, msg.msg_id + 100)));
+            })
+        };
+        let _guard1 = {
+            let log = Arc::clone(&log);
+            broker.on(move |msg: Arc<TestMsg1>| log.lock().unwrap().push((1, msg.msg_id)))
+        };
+
+        let _ = broker.publish_message(Arc::new(TestMsg0::new(0, 0)));
+
+        let log = log.lock().unwrap().clone();
+        log
+    };
+
+    assert_eq!(
+        vec![(0, 0), (1, 100), (0, 10), (1, 110)],
+        run(DeliveryMode::Queued)
+    );
+    assert_eq!(
+        vec![(0, 0), (0, 10), (1, 100), (1, 110)],
+        run(DeliveryMode::Direct)
+    );
+}
+
+#[test]
+fn test_typed_publisher() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0: TypedPublisher<TestMsg0> = broker.publisher();
+    let pub1 = pub0.clone();
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+    let dead_letters: Subscription<DeadLetter> = Subscription::new(Arc::clone(&broker));
+
+    assert!(pub0.publish(TestMsg0::new(0, 0)).is_ok());
+    assert!(pub1.publish(Arc::new(TestMsg0::new(1, 1))).is_ok());
+    std::thread::spawn(move || {
+        let _ = pub1.publish(TestMsg0::new(1, 2));
+    })
+    .join()
+    .unwrap();
+
+    let mut data = vec![];
+    sub0.process_messages(|msg| data.push((msg.pub_id, msg.msg_id)));
+    assert_eq!(vec![(0, 0), (1, 1), (1, 2)], data);
+
+    let _ = sub0.deactivate();
+    let _ = TypedPublisher::<TestMsg1>::new(Arc::clone(&broker)).publish(TestMsg1::new(0, 3));
+    let mut reasons = vec![];
+    dead_letters.process_messages(|dead_letter| reasons.push(dead_letter.reason()));
+    assert_eq!(vec![DeadLetterReason::NoSubscribers], reasons);
+}
+
+#[test]
+fn test_publish_value_and_batch() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = TestPublisher::new(Arc::clone(&broker));
+    let mut sub0 = TestSubsciber0::new();
+    sub0.subscribe(Arc::clone(&broker));
+
+    {
+        pub0.publish_value(TestMsg0::new(0, 0));
+        let _ = broker.publish_value(TestMsg2::new(0, 1));
+
+        sub0.process_messages();
+        assert_eq!(vec![(0, 0, 0), (0, 2, 1)], sub0.data);
+        sub0.data.clear();
+    }
+
+    {
+        pub0.publish_batch([
+            Arc::new(TestMsg0::new(0, 2)) as Arc<dyn Message>,
+            Arc::new(TestMsg2::new(0, 3)),
+            Arc::new(TestMsg1::new(0, 4)),
+            Arc::new(TestMsg0::new(0, 5)),
+        ]);
+        let _ = broker
+            .publisher::<TestMsg2>()
+            .publish_batch([TestMsg2::new(1, 6), TestMsg2::new(1, 7)]);
+
+        sub0.process_messages();
+        assert_eq!(
+            vec![(0, 0, 2), (0, 0, 5), (0, 2, 3), (1, 2, 6), (1, 2, 7)],
+            sub0.data
+        );
+    }
+
+    {
+        let mut multi_sub = MultiSubscription::unregistered();
+        multi_sub
+            .add::<TestMsg0>()
+            .add::<TestMsg1>()
+            .set_receive_strategy(ReceiveStrategy::PublishOrder);
+        let _ = multi_sub.register(Arc::clone(&broker));
+
+        let _ = broker.publish_message_batch(vec![
+            Arc::new(TestMsg0::new(0, 0)) as Arc<dyn Message>,
+            Arc::new(TestMsg1::new(0, 1)),
+            Arc::new(TestMsg0::new(0, 2)),
+            Arc::new(TestMsg1::new(0, 3)),
+        ]);
+        assert_eq!(
+            vec![(0, 0), (1, 1), (0, 2), (1, 3)],
+            recv_msg_ids(&multi_sub)
+        );
+    }
+
+    // Concurrent batches put messages into each channel in the order of their sequence numbers.
+    {
+        let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+        let sub1: Subscription<TestMsg1> = Subscription::new(Arc::clone(&broker));
+
+        let publishers: Vec<_> = (0..4)
+            .map(|_| {
+                let broker = Arc::clone(&broker);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let _ = broker.publish_message_batch(vec![
+                            Arc::new(TestMsg0::new(0, i)) as Arc<dyn Message>,
+                            Arc::new(TestMsg1::new(0, i)),
+                        ]);
+                    }
+                })
+            })
+            .collect();
+        publishers
+            .into_iter()
+            .for_each(|publisher| publisher.join().unwrap());
+
+        let mut seqs = vec![];
+        while let Some(seq) = sub0.peek_sequence_number() {
+            seqs.push(seq);
+            let _ = sub0.try_recv_message();
+        }
+        assert_eq!(400, seqs.len());
+        assert!(seqs.windows(2).all(|seqs| seqs[0] < seqs[1]));
+
+        let mut seqs = vec![];
+        while let Some(seq) = sub1.peek_sequence_number() {
+            seqs.push(seq);
+            let _ = sub1.try_recv_message();
+        }
+        assert_eq!(400, seqs.len());
+        assert!(seqs.windows(2).all(|seqs| seqs[0] < seqs[1]));
+    }
+}
+
+#[test]
+fn test_errors() -> Result<(), Box<dyn std::error::Error>> {
+    fn assert_error<E: std::error::Error + Send + Sync + 'static>() {}
+    assert_error::<MessageBrokerError>();
+    assert_error::<MessageTopicError>();
+    assert_error::<MessageChannelError>();
+    assert_error::<MessageHandlerError>();
+    assert_error::<SubscriptionError>();
+
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+    let _ = sub0.pause(Some(0));
+
+    let err = broker
+        .publish_value(TestMsg0::new(0, 0))
+        .expect_err("the message doesn't fit into the paused subscription");
+    assert_eq!("failed to deliver the message", err.to_string());
+
+    let msg_topic_err = std::error::Error::source(&err).unwrap();
+    assert_eq!(
+        "failed to send the message to a message channel",
+        msg_topic_err.to_string()
+    );
+    assert_eq!(
+        format!(
+            "the paused message channel is full and rejected a message of type `{}`",
+            std::any::type_name::<TestMsg0>()
+        ),
+        msg_topic_err.source().unwrap().to_string()
+    );
+
+    let err = MessageBrokerError::other("custom error");
+    assert_eq!("custom error", err.to_string());
+
+    let _ = sub0.resume();
+    broker.publish_value(TestMsg0::new(0, 1))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_broker_shutdown() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0: TypedPublisher<TestMsg0> = broker.publisher();
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+    let sub1: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>().add::<TestMsg1>();
+    let _ = multi_sub.register(Arc::clone(&broker));
+
+    {
+        for i in 0..3 {
+            let _ = pub0.publish(TestMsg0::new(0, i));
+        }
+        assert!(matches!(
+            sub0.try_recv_message(),
+            Ok(msg) if msg.msg_id == 0
+        ));
+        assert!(multi_sub.recv_message().is_some());
+
+        let summary = broker.shutdown(ShutdownMode::Drain).unwrap();
+        assert!(broker.is_shut_down());
+        assert_eq!(4, summary.closed_channels);
+        assert_eq!(7, summary.pending_messages);
+        assert!(summary.discarded_messages.is_empty());
+    }
+
+    {
+        assert!(matches!(
+            pub0.publish(TestMsg0::new(0, 3)),
+            Err(MessageBrokerError::MessageTopicError(
+                MessageTopicError::Closed { .. }
+            ))
+        ));
+        assert!(broker.publish_value(TestMsg2::new(0, 4)).is_err());
+
+        assert!(sub0.is_closed());
+        let mut msg_ids = vec![];
+        while let Ok(msg) = sub0.try_recv_message() {
+            msg_ids.push(msg.msg_id);
+        }
+        assert_eq!(vec![1, 2], msg_ids);
+        assert!(matches!(sub0.try_recv_message(), Err(TryRecvError::Closed)));
+
+        assert!(multi_sub.is_closed());
+        assert_eq!(2, multi_sub.pending_messages());
+    }
+
+    {
+        let summary = broker.shutdown(ShutdownMode::Discard).unwrap();
+        assert_eq!(5, summary.discarded_messages.len());
+        assert!(format!("{:?}", summary).starts_with("ShutdownSummary { closed_channels: "));
+        assert!(matches!(sub1.try_recv_message(), Err(TryRecvError::Closed)));
+        assert!(matches!(
+            multi_sub.try_recv_message(),
+            Err(TryRecvError::Closed)
+        ));
+
+        let late_sub: Subscription<TestMsg2> = Subscription::new(Arc::clone(&broker));
+        assert!(matches!(
+            late_sub.try_recv_message(),
+            Err(TryRecvError::Closed)
+        ));
+        assert!(matches!(
+            Subscription::<TestMsg2>::unregistered().try_recv_message(),
+            Err(TryRecvError::NotRegistered)
+        ));
+    }
+
+    {
+        let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+        broker.set_delivery_mode::<TestMsg0>(DeliveryMode::Direct);
+        let data = Arc::new(std::sync::Mutex::new(vec![]));
+        let _guard = {
+            let data = Arc::clone(&data);
+            broker.on(move |msg: Arc<TestMsg0>| data.lock().unwrap().push(msg.msg_id))
+        };
+
+        assert!(broker.publish_value(TestMsg0::new(0, 0)).is_ok());
+        assert!(broker.shutdown(ShutdownMode::Discard).is_ok());
+        assert!(broker.publish_value(TestMsg0::new(0, 1)).is_err());
+        assert!(broker
+            .publish_batch([Arc::new(TestMsg0::new(0, 2)) as Arc<dyn Message>])
+            .is_err());
+        assert_eq!(vec![0], *data.lock().unwrap());
+    }
+}
+
+#[test]
+fn test_weak_broker_references() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+    let weak_broker = Arc::downgrade(&broker);
+
+    let dead_letters: Subscription<DeadLetter> = Subscription::new_weak(&broker);
+    let sub0: Subscription<TestMsg0> = Subscription::new_weak(&broker);
+    let mut multi_sub = MultiSubscription::unregistered();
+    multi_sub.add::<TestMsg0>();
+    let _ = multi_sub.register_weak(&broker);
+    multi_sub.add::<TestMsg1>();
+
+    {
+        assert_eq!(1, Arc::strong_count(&broker));
+        assert!(Arc::ptr_eq(&broker, &sub0.message_broker().unwrap()));
+
+        let _ = broker.publish_value(TestMsg0::new(0, 0));
+        let _ = broker.publish_value(TestMsg1::new(0, 1));
+        assert_eq!(0, sub0.recv_message().unwrap().msg_id);
+        assert_eq!(2, multi_sub.pending_messages());
+
+        drop(sub0);
+        let _ = multi_sub.unregister();
+        let _ = broker.publish_value(TestMsg0::new(0, 2));
+        assert_eq!(
+            DeadLetterReason::NoSubscribers,
+            dead_letters.recv_message().unwrap().reason()
+        );
+    }
+
+    {
+        let sub1: Subscription<TestMsg1> = Subscription::new_weak(&broker);
+        let mut multi_sub = MultiSubscription::unregistered();
+        multi_sub.add::<TestMsg1>().add::<TestMsg2>();
+        let _ = multi_sub.register_weak(&broker);
+        let _ = broker.publish_value(TestMsg1::new(0, 3));
+        let _ = broker.publish_value(TestMsg2::new(0, 4));
+
+        drop(broker);
+        assert!(weak_broker.upgrade().is_none());
+
+        assert!(sub1.is_broker_gone());
+        assert!(sub1.message_broker().is_none());
+        assert_eq!(3, sub1.try_recv_message().unwrap().msg_id);
+        assert!(matches!(
+            sub1.try_recv_message(),
+            Err(TryRecvError::BrokerGone)
+        ));
+
+        let mut sub1 = sub1;
+        assert!(matches!(
+            sub1.unregister(),
+            Err(SubscriptionError::BrokerGone)
+        ));
+        assert!(!sub1.is_registered());
+
+        assert!(multi_sub.remove::<TestMsg1>().is_ok());
+        assert!(!multi_sub.contains::<TestMsg1>());
+        assert_eq!(vec![(2, 4)], recv_msg_ids(&multi_sub));
+    }
+}
+
+#[test]
+fn test_topic_garbage_collection() {
+    let default_broker = Arc::new(DefaultMessageBroker::new());
+    let broker: Arc<dyn MessageBroker> = default_broker.clone();
+
+    {
+        let _ = broker.publish_value(TestMsg0::new(0, 0));
+        let _ = broker.publish_batch([Arc::new(TestMsg1::new(0, 1)) as Arc<dyn Message>]);
+        assert_eq!(0, default_broker.topic_count());
+    }
+
+    {
+        let mut sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+        let sub1: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+        assert_eq!(1, default_broker.topic_count());
+
+        let _ = sub0.unregister();
+        assert_eq!(1, default_broker.topic_count());
+        drop(sub1);
+        assert_eq!(0, default_broker.topic_count());
+
+        let _ = sub0.register(Arc::clone(&broker));
+        let _ = broker.publish_value(TestMsg0::new(0, 2));
+        assert_eq!(2, sub0.recv_message().unwrap().msg_id);
+    }
+
+    {
+        let pub1: TypedPublisher<TestMsg1> = broker.publisher();
+        let sub1: Subscription<TestMsg1> = Subscription::new(Arc::clone(&broker));
+        drop(sub1);
+        assert_eq!(1, default_broker.topic_count());
+
+        let sub1: Subscription<TestMsg1> = Subscription::new(Arc::clone(&broker));
+        let _ = pub1.publish(TestMsg1::new(0, 3));
+        assert_eq!(3, sub1.recv_message().unwrap().msg_id);
+        drop(sub1);
+
+        assert_eq!(0, default_broker.compact());
+        drop(pub1);
+        assert_eq!(1, default_broker.compact());
+        assert_eq!(0, default_broker.topic_count());
+    }
+
+    {
+        broker.set_delivery_mode::<TestMsg2>(DeliveryMode::Direct);
+        let guard = broker.on(|_: Arc<TestMsg2>| {});
+        drop(guard);
+        assert_eq!(0, default_broker.compact());
+
+        broker.set_delivery_mode::<TestMsg2>(DeliveryMode::Queued);
+        assert_eq!(1, default_broker.compact());
+    }
+}
+
+#[test]
+fn test_ambient_brokers() {
+    let broker0: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+    let broker1: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+
+    let pub0 = AmbientPublisher::new();
+
+    // The global broker is only read, since other tests run in parallel.
+    {
+        assert!(Arc::ptr_eq(&global_broker(), &global_broker()));
+        assert!(Arc::ptr_eq(&global_broker(), &current_broker()));
+    }
+
+    {
+        let (sub0, mut multi_sub) = with_broker(Arc::clone(&broker0), || {
+            let sub0: Subscription<TestMsg0> = Subscription::ambient();
+            let mut multi_sub = MultiSubscription::ambient();
+            multi_sub.add::<TestMsg0>();
+            pub0.publish_value(TestMsg0::new(0, 0));
+
+            (sub0, multi_sub)
+        });
+
+        assert!(Arc::ptr_eq(&broker0, &multi_sub.message_broker().unwrap()));
+        assert_eq!(0, sub0.recv_message().unwrap().msg_id);
+        assert_eq!(vec![(0, 0)], recv_msg_ids(&multi_sub));
+
+        multi_sub.add::<TestMsg2>();
+        let _ = broker0.publish_value(TestMsg2::new(0, 1));
+        assert_eq!(vec![(2, 1)], recv_msg_ids(&multi_sub));
+    }
+
+    {
+        let (sub0, sub1) = with_broker(Arc::clone(&broker0), || {
+            let sub0: Subscription<TestMsg1> = Subscription::ambient();
+            let sub1 = with_broker(Arc::clone(&broker1), || {
+                assert!(Arc::ptr_eq(&broker1, &current_broker()));
+                let sub1: Subscription<TestMsg1> = Subscription::ambient();
+                pub0.publish_value(TestMsg1::new(0, 1));
+
+                sub1
+            });
+            assert!(Arc::ptr_eq(&broker0, &current_broker()));
+            pub0.publish_value(TestMsg1::new(0, 2));
+
+            (sub0, sub1)
+        });
+
+        assert!(!Arc::ptr_eq(&broker0, &current_broker()));
+        assert_eq!(2, sub0.recv_message().unwrap().msg_id);
+        assert!(sub0.recv_message().is_none());
+        assert_eq!(1, sub1.recv_message().unwrap().msg_id);
+        assert!(sub1.recv_message().is_none());
+    }
+
+    {
+        let mut pub1 = AmbientPublisher::new();
+        pub1.set_message_broker(Arc::clone(&broker1));
+        let sub1: Subscription<TestMsg2> = Subscription::new(Arc::clone(&broker1));
+
+        with_broker(Arc::clone(&broker0), || {
+            pub1.publish_value(TestMsg2::new(1, 3))
+        });
+        assert_eq!(3, sub1.recv_message().unwrap().msg_id);
+    }
+}
+
+#[derive(Debug)]
+struct TestLocalMsg {
+    log: Rc<RefCell<Vec<u32>>>,
+    msg_id: u32,
+}
+
+impl LocalMessage for TestLocalMsg {}
+
+#[test]
+fn test_local_broker() {
+    let broker = Rc::new(LocalMessageBroker::new());
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    let mut sub0: LocalSubscription<TestLocalMsg> = LocalSubscription::new(Rc::clone(&broker));
+    let sub1: LocalSubscription<TestLocalMsg> = LocalSubscription::new(Rc::clone(&broker));
+    let sub2: LocalSubscription<TestLocalMsg> = LocalSubscription::unregistered();
+    assert!(sub0.is_registered() && sub0.is_active());
+    assert!(!sub2.is_registered());
+    assert!(matches!(
+        sub0.register(Rc::clone(&broker)),
+        Err(SubscriptionError::AlreadyRegistered)
+    ));
+
+    for msg_id in 0..3 {
+        broker.publish_value(TestLocalMsg {
+            log: Rc::clone(&log),
+            msg_id,
+        });
+    }
+    assert_eq!(3, sub0.pending_messages());
+
+    sub0.process_messages(|msg| {
+        msg.log.borrow_mut().push(msg.msg_id);
+        if msg.msg_id == 0 {
+            broker.publish_value(TestLocalMsg {
+                log: Rc::clone(&msg.log),
+                msg_id: 3,
+            });
+        }
+    });
+    assert_eq!(vec![0, 1, 2, 3], *log.borrow());
+
+    sub1.message_iter()
+        .handle(|msg: Rc<TestLocalMsg>| msg.log.borrow_mut().push(msg.msg_id * 10))
+        .run();
+    assert_eq!(vec![0, 1, 2, 3, 0, 10, 20, 30], *log.borrow());
+
+    sub1.deactivate().unwrap();
+    broker.publish_value(TestLocalMsg {
+        log: Rc::clone(&log),
+        msg_id: 4,
+    });
+    assert!(sub1.recv_message().is_none());
+    sub1.activate().unwrap();
+
+    let ids: Vec<u32> = sub0
+        .message_iter()
+        .chain(sub1.message_iter())
+        .filter_type::<TestLocalMsg>()
+        .map(|msg| msg.msg_id)
+        .collect();
+    assert_eq!(vec![4], ids);
+
+    sub0.unregister().unwrap();
+    drop(sub1);
+    broker.publish_value(TestLocalMsg {
+        log: Rc::clone(&log),
+        msg_id: 5,
+    });
+    assert!(sub0.recv_message().is_none());
+    // Messages which nobody received are dropped with their `Rc`s.
+    assert_eq!(1, Rc::strong_count(&log));
+}
+
+static SPIN_BROKER: SpinMessageBroker = SpinMessageBroker::new();
+
+#[test]
+fn test_spin_broker() {
+    assert!(matches!(
+        SPIN_BROKER.publish_value(TestMsg0::new(0, 0)),
+        Err(SpinMessageBrokerError::NoSubscribers { .. })
+    ));
+
+    let sub0: SpinSubscription<TestMsg0> = SpinSubscription::new(&SPIN_BROKER, 2);
+    let sub1: SpinSubscription<TestMsg0> = SpinSubscription::new(&SPIN_BROKER, 4);
+    let sub2: SpinSubscription<TestMsg1> = SpinSubscription::new(&SPIN_BROKER, 0);
+    assert_eq!(1, sub2.capacity());
+
+    SPIN_BROKER.publish_value(TestMsg0::new(0, 1)).unwrap();
+    SPIN_BROKER.publish_value(TestMsg0::new(0, 2)).unwrap();
+    assert!(matches!(
+        SPIN_BROKER.publish_value(TestMsg0::new(0, 3)),
+        Err(SpinMessageBrokerError::BufferFull { .. })
+    ));
+    assert_eq!(2, sub0.pending_messages());
+    assert_eq!(3, sub1.pending_messages());
+
+    assert_eq!(1, sub0.recv_message().unwrap().msg_id);
+    SPIN_BROKER.publish_value(TestMsg0::new(0, 4)).unwrap();
+    let ids: Vec<u32> = sub0
+        .message_iter()
+        .filter_type::<TestMsg0>()
+        .map(|msg| msg.msg_id)
+        .collect();
+    assert_eq!(vec![2, 4], ids);
+
+    sub1.deactivate();
+    SPIN_BROKER.publish_value(TestMsg0::new(0, 5)).unwrap();
+    sub1.activate();
+    let mut ids = vec![];
+    sub1.message_iter()
+        .handle(|msg: Arc<TestMsg0>| ids.push(msg.msg_id))
+        .run();
+    assert_eq!(vec![1, 2, 3, 4], ids);
+
+    SPIN_BROKER.publish_value(TestMsg1::new(0, 6)).unwrap();
+    assert_eq!(6, sub2.recv_message().unwrap().msg_id);
+
+    let spin_broker = Arc::new(SpinMessageBroker::new());
+    {
+        let sub3: SpinSubscription<TestMsg0> = SpinSubscription::new(&spin_broker, 1);
+        spin_broker.publish_value(TestMsg0::new(1, 7)).unwrap();
+        assert_eq!(7, sub3.recv_message().unwrap().msg_id);
+    }
+    assert!(spin_broker.publish_value(TestMsg0::new(1, 8)).is_err());
+}
+
+#[derive(Debug)]
+struct TestExpiringMsg {
+    msg_id: u32,
+    ttl: Duration,
+}
+
+impl Message for TestExpiringMsg {
+    fn ttl(&self) -> Option<Duration> {
+        Some(self.ttl)
+    }
+}
+
+#[test]
+fn test_message_ttl() {
+    let clock = Arc::new(MockClock::new());
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::with_clock(clock.clone()));
+
+    {
+        let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+        let sub1: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+        broker.set_message_ttl::<TestMsg0>(Some(Duration::from_millis(20)));
+
+        broker.publish_value(TestMsg0::new(0, 0)).unwrap();
+        assert_eq!(0, sub0.recv_message().unwrap().msg_id);
+        broker.publish_value(TestMsg0::new(0, 1)).unwrap();
+        clock.advance(Duration::from_millis(20));
+        broker.publish_value(TestMsg0::new(0, 2)).unwrap();
+
+        assert_eq!(2, sub0.recv_message().unwrap().msg_id);
+        assert_eq!(1, sub0.expired_messages());
+        assert_eq!(2, sub1.recv_message().unwrap().msg_id);
+        assert_eq!(2, sub1.expired_messages());
+    }
+
+    {
+        let dead_letters: Subscription<DeadLetter> = Subscription::new(Arc::clone(&broker));
+        let sub0: Subscription<TestExpiringMsg> = Subscription::new(Arc::clone(&broker));
+        broker.set_message_ttl::<TestExpiringMsg>(Some(Duration::from_millis(20)));
+        broker.set_expiry_policy::<TestExpiringMsg>(ExpiryPolicy::DeadLetter);
+
+        broker
+            .publish_value(TestExpiringMsg {
+                msg_id: 0,
+                ttl: Duration::from_millis(1),
+            })
+            .unwrap();
+        broker
+            .publish_value(TestExpiringMsg {
+                msg_id: 1,
+                ttl: Duration::from_secs(60),
+            })
+            .unwrap();
+        clock.advance(Duration::from_millis(20));
+
+        assert_eq!(1, sub0.recv_message().unwrap().msg_id);
+        assert_eq!(1, sub0.expired_messages());
+
+        let dead_letter = dead_letters.recv_message().unwrap();
+        assert_eq!(DeadLetterReason::Expired, dead_letter.reason());
+        let msg = dead_letter.message();
+        let msg = (*msg)
+            .as_any_ref()
+            .downcast_ref::<TestExpiringMsg>()
+            .unwrap();
+        assert_eq!(0, msg.msg_id);
+        assert!(dead_letters.recv_message().is_none());
+    }
+
+    {
+        let dead_letters: Subscription<DeadLetter> = Subscription::new(Arc::clone(&broker));
+        let received = Arc::new(std::sync::Mutex::new(vec![]));
+        // A message published by the callback itself waits in its queue until it returns.
+        let _guard = {
+            let received = Arc::clone(&received);
+            let msg_broker = Arc::clone(&broker);
+            let clock = Arc::clone(&clock);
+            broker.on(move |msg: Arc<TestExpiringMsg>| {
+                received.lock().unwrap().push(msg.msg_id);
+                if msg.msg_id == 0 {
+                    let _ = msg_broker.publish_value(TestExpiringMsg {
+                        msg_id: 1,
+                        ttl: Duration::from_millis(1),
+                    });
+                    clock.advance(Duration::from_millis(1));
+                }
+            })
+        };
+
+        broker
+            .publish_value(TestExpiringMsg {
+                msg_id: 0,
+                ttl: Duration::from_secs(60),
+            })
+            .unwrap();
+        assert_eq!(vec![0], *received.lock().unwrap());
+
+        let dead_letter = dead_letters.recv_message().unwrap();
+        assert_eq!(DeadLetterReason::Expired, dead_letter.reason());
+        assert!(dead_letters.recv_message().is_none());
+    }
+}
+
+#[test]
+fn test_scheduler() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+    let clock = Arc::new(MockClock::new());
+    let scheduler = Scheduler::with_clock(Arc::clone(&broker), clock.clone());
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+    let sub1: Subscription<TestMsg1> = Subscription::new(Arc::clone(&broker));
+
+    scheduler.publish_after(Duration::from_millis(500), TestMsg0::new(0, 1));
+    scheduler.publish_at(
+        clock.now() + Duration::from_millis(200),
+        TestMsg0::new(0, 0),
+    );
+    let cancelled = scheduler.publish_after(Duration::from_millis(300), TestMsg0::new(0, 2));
+    let mut next_id = 0;
+    let ticks = scheduler.publish_every(Duration::from_secs(1), move || {
+        next_id += 1;
+        TestMsg1::new(1, next_id)
+    });
+    assert_eq!(4, scheduler.len());
+
+    cancelled.cancel();
+    assert!(cancelled.is_cancelled());
+    assert_eq!(3, scheduler.len());
+
+    clock.advance(Duration::from_millis(100));
+    scheduler.run_pending();
+    assert!(sub0.recv_message().is_none());
+
+    clock.advance(Duration::from_millis(400));
+    scheduler.run_pending();
+    assert_eq!(0, sub0.recv_message().unwrap().msg_id);
+    assert_eq!(1, sub0.recv_message().unwrap().msg_id);
+    assert!(sub0.recv_message().is_none());
+    assert!(sub1.recv_message().is_none());
+
+    clock.advance(Duration::from_millis(500));
+    scheduler.run_pending();
+    assert_eq!(1, sub1.recv_message().unwrap().msg_id);
+
+    // Missed periods are skipped.
+    clock.advance(Duration::from_millis(3500));
+    scheduler.run_pending();
+    assert_eq!(2, sub1.recv_message().unwrap().msg_id);
+    assert!(sub1.recv_message().is_none());
+
+    ticks.cancel();
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(0, scheduler.run_pending());
+    assert!(scheduler.is_empty());
+    assert!(sub1.recv_message().is_none());
+
+    // The timer thread publishes messages on its own.
+    let scheduler = Scheduler::new(Arc::clone(&broker));
+    scheduler.publish_after(Duration::from_millis(10), TestMsg0::new(0, 3));
+    let mut msg = None;
+    for _ in 0..100 {
+        msg = sub0.recv_message();
+        if msg.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(3, msg.unwrap().msg_id);
+}
+
+#[test]
+#[should_panic(expected = "the period of a repeating message must be greater than zero")]
+fn test_scheduler_rejects_zero_period() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+    let scheduler = Scheduler::with_clock(broker, Arc::new(MockClock::new()));
+
+    scheduler.publish_every(Duration::ZERO, || TestMsg0::new(0, 0));
+}
+
+#[test]
+fn test_scheduler_survives_panics_and_overflows() {
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::new());
+    let clock = Arc::new(MockClock::new());
+    let scheduler = Scheduler::with_clock(Arc::clone(&broker), clock.clone());
+    let sub0: Subscription<TestMsg0> = Subscription::new(Arc::clone(&broker));
+
+    // A panicking factory skips its message, but the task keeps running.
+    let mut next_id = 0;
+    scheduler.publish_every(Duration::from_secs(1), move || {
+        next_id += 1;
+        if next_id == 1 {
+            panic!("the factory panicked");
+        }
+        TestMsg0::new(0, next_id)
+    });
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(0, scheduler.run_pending());
+    assert_eq!(1, scheduler.len());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(1, scheduler.run_pending());
+    assert_eq!(2, sub0.recv_message().unwrap().msg_id);
+
+    // Deadlines which overflow `Instant` are never due.
+    scheduler.publish_after(Duration::MAX, TestMsg0::new(0, 10));
+    scheduler.publish_every(Duration::MAX, || TestMsg0::new(0, 11));
+    assert_eq!(3, scheduler.len());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(1, scheduler.run_pending());
+    assert_eq!(3, sub0.recv_message().unwrap().msg_id);
+    assert!(sub0.recv_message().is_none());
+    assert_eq!(3, scheduler.len());
+
+    // The timer thread waits for them without running out of time.
+    let scheduler = Scheduler::new(Arc::clone(&broker));
+    scheduler.publish_after(Duration::MAX, TestMsg0::new(0, 12));
+    drop(scheduler);
+    assert!(sub0.recv_message().is_none());
+}
+
+#[derive(Debug)]
+struct TestEventMsg {
+    event_id: u64,
+    msg_id: u32,
+}
+
+impl Message for TestEventMsg {
+    fn message_id(&self) -> Option<u64> {
+        Some(self.event_id)
+    }
+}
+
+#[test]
+fn test_message_deduplication() {
+    let clock = Arc::new(MockClock::new());
+    let broker: Arc<dyn MessageBroker> = Arc::new(DefaultMessageBroker::with_clock(clock.clone()));
+    let sub0: Subscription<TestEventMsg> = Subscription::new(Arc::clone(&broker));
+    let publish = |event_id, msg_id| broker.publish_value(TestEventMsg { event_id, msg_id });
+
+    {
+        publish(0, 0).unwrap();
+        publish(0, 1).unwrap();
+        assert_eq!(2, sub0.pending_messages());
+        assert_eq!(DedupStats::default(), broker.dedup_stats::<TestEventMsg>());
+        sub0.message_iter().run();
+    }
+
+    {
+        broker.set_dedup_window::<TestEventMsg>(Some(DedupWindow::Count(2)));
+        publish(0, 0).unwrap();
+        publish(1, 1).unwrap();
+        publish(0, 2).unwrap();
+        publish(2, 3).unwrap();
+        // The id 0 has left the window.
+        publish(0, 4).unwrap();
+        publish(2, 5).unwrap();
+
+        let ids: Vec<u32> = sub0
+            .message_iter()
+            .filter_type::<TestEventMsg>()
+            .map(|msg| msg.msg_id)
+            .collect();
+        assert_eq!(vec![0, 1, 3, 4], ids);
+        assert_eq!(
+            DedupStats {
+                unique: 4,
+                duplicates: 2
+            },
+            broker.dedup_stats::<TestEventMsg>()
+        );
+    }
+
+    {
+        broker.set_dedup_window::<TestEventMsg>(Some(DedupWindow::Duration(
+            Duration::from_millis(20),
+        )));
+        broker.set_delivery_mode::<TestEventMsg>(DeliveryMode::Direct);
+        let received = Arc::new(std::sync::Mutex::new(vec![]));
+        let _guard = broker.on({
+            let received = Arc::clone(&received);
+            move |msg: Arc<TestEventMsg>| received.lock().unwrap().push(msg.msg_id)
+        });
+
+        // The callback is the only receiver of the messages.
+        let _ = sub0.deactivate();
+        publish(3, 6).unwrap();
+        publish(3, 7).unwrap();
+        clock.advance(Duration::from_millis(20));
+        publish(3, 8).unwrap();
+        let _ = sub0.activate();
+
+        assert_eq!(vec![6, 8], *received.lock().unwrap());
+        assert_eq!(0, sub0.pending_messages());
+    }
+
+    {
+        broker.set_dedup_window::<TestEventMsg>(Some(DedupWindow::Count(8)));
+        let stats = broker.dedup_stats::<TestEventMsg>();
+
+        // Messages which nobody received can be published again.
+        let _ = sub0.deactivate();
+        let _ = publish(4, 9);
+        let _ = sub0.activate();
+        let _ = sub0.pause(Some(0));
+        assert!(publish(4, 10).is_err());
+        let _ = sub0.resume();
+        publish(4, 11).unwrap();
+        publish(4, 12).unwrap();
+
+        let ids: Vec<u32> = sub0
+            .message_iter()
+            .filter_type::<TestEventMsg>()
+            .map(|msg| msg.msg_id)
+            .collect();
+        assert_eq!(vec![11], ids);
+        assert_eq!(
+            DedupStats {
+                unique: stats.unique + 1,
+                duplicates: stats.duplicates + 1
+            },
+            broker.dedup_stats::<TestEventMsg>()
+        );
+    }
+}